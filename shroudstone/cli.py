@@ -3,13 +3,15 @@
 To get started renaming your replays, use [b]rename-replays --help[/b] to view
 options or [b]rename-replays[/b] to jump straight in."""
 
+from datetime import datetime
+import io
 import logging
 import os
 from pathlib import Path
 import platform
 import subprocess
 import sys
-from typing import Optional
+from typing import List, Optional
 from typing_extensions import Annotated
 
 import typer
@@ -46,11 +48,138 @@ def callback(
 
 
 @app.command(rich_help_panel="Tools for nerds")
-def get_replay_info(replay_file: typer.FileBinaryRead):
+def get_replay_info(
+    replay_file: typer.FileBinaryRead,
+    recover: Annotated[
+        bool, typer.Option(help="Salvage what we can from truncated/corrupt replays")
+    ] = False,
+    scrub: Annotated[
+        bool,
+        typer.Option(
+            help="Hash the UUIDs and drop the discriminators of players other "
+            "than you (see my_uuids in the config file)"
+        ),
+    ] = False,
+    salt: Annotated[
+        Optional[str],
+        typer.Option(
+            help="Secret salt for the UUID hashes used by --scrub (default: a "
+            "random one, reported on stderr)"
+        ),
+    ] = None,
+):
     """Extract information from a replay, outputting it in JSON format."""
-    from shroudstone.replay import summarize_replay
+    from shroudstone.replay import GameState, summarize_state
+    from shroudstone.scrub import new_salt, scrub_summary
+
+    state = GameState.at_end_of(replay_file, recover=recover)
+    info = summarize_state(state)
+    if scrub:
+        if not salt:
+            salt = new_salt()
+            typer.echo(f"Hashed UUIDs with salt {salt}", err=True)
+        keep_me = list(Config.load().my_uuids)
+        if state.recorded_by is not None:
+            keep_me.append(state.recorded_by)
+        info = scrub_summary(info, keep_me=keep_me, salt=salt)
+    typer.echo(info.model_dump_json(indent=2))
+
+
+@app.command(rich_help_panel="Tools for nerds")
+def info(replay_file: typer.FileBinaryRead):
+    """Print a short human-readable summary of a replay (use - to read from stdin)."""
+    from shroudstone.replay import summarize_all
+
+    summaries = summarize_all(replay_file)
+    for i, summary in enumerate(summaries, start=1):
+        if len(summaries) > 1:
+            if i > 1:
+                typer.echo()
+            typer.echo(f"Game {i} of {len(summaries)}:")
+        typer.echo(str(summary))
+
+
+@app.command(rich_help_panel="Tools for nerds")
+def explain(
+    replay_file: typer.FileBinaryRead,
+    recover: Annotated[
+        bool, typer.Option(help="Salvage what we can from truncated/corrupt replays")
+    ] = False,
+):
+    """Narrate what happened in a replay, from the lobby to the end of the game."""
+    from shroudstone.explain import explain_replay
+
+    typer.echo(explain_replay(replay_file, recover=recover))
+
+
+@app.command(rich_help_panel="Tools for nerds")
+def discord_embed(replay_file: typer.FileBinaryRead):
+    """Output a Discord webhook payload (JSON) describing a replay."""
+    from shroudstone.discord import to_discord_embed_json
+    from shroudstone.replay import GameState
+
+    typer.echo(to_discord_embed_json(GameState.at_end_of(replay_file)))
+
+
+@app.command(rich_help_panel="Tools for nerds")
+def activity_heatmap(replay_file: typer.FileBinaryRead):
+    """Output the number of commands each player sent in each 10s interval, as CSV."""
+    import csv
+    from itertools import zip_longest
+    from shroudstone.replay import ACTIVITY_BUCKET_SECONDS, GameState
 
-    typer.echo(summarize_replay(replay_file).model_dump_json(indent=2))
+    state = GameState.at_end_of(replay_file)
+    players = [sp for sp in state.slot_players() if sp.client is not None]
+    writer = csv.writer(sys.stdout)
+    writer.writerow(["seconds", *(sp.display_name for sp in players)])
+    columns = [sp.client.activity for sp in players if sp.client is not None]
+    for i, row in enumerate(zip_longest(*columns, fillvalue=0)):
+        writer.writerow([i * ACTIVITY_BUCKET_SECONDS, *row])
+
+
+@app.command(rich_help_panel="Tools for nerds")
+def command_rhythm(replay_file: typer.FileBinaryRead):
+    """Output how each player's commands bunch into bursts and idle gaps, as CSV."""
+    import csv
+    from shroudstone.replay import GameState
+
+    def number(value: Optional[float]) -> str:
+        return "" if value is None else f"{value:.2f}"
+
+    state = GameState.at_end_of(replay_file)
+    writer = csv.writer(sys.stdout)
+    writer.writerow(
+        ["player", "commands", "bursts", "mean_burst", "mean_idle", "longest_idle"]
+    )
+    for sp in state.slot_players():
+        if sp.client is None:
+            continue
+        c = sp.client
+        writer.writerow(
+            [
+                sp.display_name,
+                sum(c.activity),
+                c.bursts,
+                number(c.mean_burst_size),
+                number(c.mean_idle_seconds),
+                number(c.longest_idle_seconds),
+            ]
+        )
+
+
+@app.command(rich_help_panel="Tools for nerds")
+def command_usage(replay_file: typer.FileBinaryRead):
+    """Output how many times each player used each type of command, as CSV."""
+    import csv
+    from shroudstone.replay import GameState, track_command_usage
+
+    state = GameState.at_end_of(replay_file, handlers=[track_command_usage])
+    players = [sp for sp in state.slot_players() if sp.client is not None]
+    columns = [sp.client.command_usage for sp in players if sp.client is not None]
+    writer = csv.writer(sys.stdout)
+    writer.writerow(["command", *(sp.display_name for sp in players)])
+    for tag in sorted(set().union(*columns)):
+        writer.writerow([tag, *(usage.get(tag, 0) for usage in columns)])
 
 
 @app.command(rich_help_panel="Tools for nerds")
@@ -67,15 +196,409 @@ def split_replay(replay_file: typer.FileBinaryRead, output_directory: Path):
     )
 
 
+@app.command(rich_help_panel="Tools for nerds")
+def decompress_replay(replay_file: typer.FileBinaryRead, output_file: Path):
+    """Strip the header from a replay and write out the decompressed message stream."""
+    from shroudstone.replay import decompress_replay
+
+    n = decompress_replay(replay_file, output_file)
+    typer.echo(f"Wrote {n} bytes of decompressed replay data to {output_file}.")
+
+
+@app.command(rich_help_panel="Tools for nerds")
+def repack_replay(
+    raw_file: Annotated[Path, typer.Argument(exists=True, dir_okay=False)],
+    output_file: Path,
+    build_number: int,
+    template: Annotated[
+        Optional[Path],
+        typer.Option(
+            exists=True,
+            dir_okay=False,
+            help="Existing replay to copy the rest of the header from",
+        ),
+    ] = None,
+):
+    """Compress a decompressed message stream back into a loadable .SGReplay file."""
+    from shroudstone.replay import repack_replay
+
+    repack_replay(raw_file, output_file, build_number, template=template)
+    typer.echo(f"Wrote repacked replay to {output_file}.")
+
+
+@app.command(rich_help_panel="Tools for nerds")
+def minimize_replay(
+    replay_file: Annotated[Path, typer.Argument(exists=True, dir_okay=False)],
+    output_file: Path,
+):
+    """Strip in-game commands from a replay, leaving a tiny file suitable for bug reports."""
+    from shroudstone.replay import minimize_replay
+
+    n = minimize_replay(replay_file, output_file)
+    typer.echo(f"Wrote {n} lobby/lifecycle messages to {output_file}.")
+
+
 @app.command(rich_help_panel="Tools for nerds")
 def dump_replay(replay_file: typer.FileBinaryRead):
     """Decode a replay and print a human-readable-ish representation of its contents."""
-    from shroudstone.replay import split_replay
-    from shroudstone.stormgate_pb2 import ReplayChunk
+    from shroudstone.replay import UnknownChunk, iter_chunks
+
+    for chunk in iter_chunks(replay_file):
+        if isinstance(chunk, UnknownChunk):
+            tag = f"unknown {chunk.tag}:"
+            print(chunk.timestamp, chunk.client_id, tag, chunk.bytes.hex())
+        else:
+            print(chunk.timestamp, chunk.client_id, chunk.inner.content)
+
+
+@app.command(rich_help_panel="Tools for nerds")
+def state_at(
+    replay_file: typer.FileBinaryRead,
+    seconds: Annotated[
+        float, typer.Argument(help="Game time (negative for the lobby)")
+    ],
+):
+    """Show who was in each slot and still connected at a point in a replay."""
+    from shroudstone.replay import GameState, format_duration
+
+    # This takes two passes (one to find the start, one up to the given time),
+    # so read the replay into memory, as stdin can't be rewound:
+    replay = io.BytesIO(replay_file.read())
+    full = GameState.at_end_of(replay)
+    if full.game_started_time is None:
+        logger.error("The game never started in this replay, so there's no time 0.")
+        raise typer.Exit(1)
+    replay.seek(0)
+    timestamp = full.game_started_time + seconds * full.ticks_per_second
+    state = GameState.state_at(replay, timestamp)
+    when = format_duration(abs(seconds), "clock")
+    typer.echo(f"{when} before the start:" if seconds < 0 else f"At {when}:")
+    for slot_number, slot in sorted(state.slots.items()):
+        typer.echo(f"  Slot {slot_number}: {slot}")
+    for client in state.clients.values():
+        status = "connected" if client.left_game_time is None else "left"
+        typer.echo(f"  {client} - {status}")
 
-    for bytestring in split_replay(replay_file):
-        chunk = ReplayChunk.FromString(bytestring)
-        print(chunk.timestamp, chunk.client_id, chunk.inner.content)
+
+@app.command(rich_help_panel="Tools for nerds")
+def describe_schema():
+    """List the message types and field numbers in shroudstone's protobuf schema."""
+    from shroudstone.replay import describe_schema
+
+    for name, fields in describe_schema().items():
+        typer.echo(name)
+        for field, number in sorted(fields.items(), key=lambda x: x[1]):
+            typer.echo(f"  {number:4d} {field}")
+
+
+@app.command(rich_help_panel="Tools for nerds")
+def profile_replays(
+    replay_dir: Annotated[
+        Optional[Path],
+        typer.Option(file_okay=False, dir_okay=True, exists=True, readable=True),
+    ] = None,
+    slowest: Annotated[
+        int, typer.Option(help="Number of replays to list, slowest first")
+    ] = 20,
+):
+    """Time how long each replay takes to parse, to help track down slow ones."""
+    from shroudstone.replay import GameState
+
+    if replay_dir is None:
+        replay_dir = get_replay_dir(Config.load())
+    states = []
+    for path in sorted(replay_dir.glob("**/*.SGReplay")):
+        try:
+            states.append((path, GameState.at_end_of(path)))
+        except Exception as e:
+            logger.warning(f"Could not parse {path}: {e}")
+    states.sort(key=lambda x: -x[1].metrics.parse_millis)
+    typer.echo(
+        f"{'parse ms':>10} {'decomp ms':>10} {'chunks':>8} "
+        f"{'KiB':>7} {'ratio':>6}  file"
+    )
+    for path, state in states[:slowest]:
+        m = state.metrics
+        kib = (state.compressed_bytes or 0) / 1024
+        ratio = state.compression_ratio or 0
+        typer.echo(
+            f"{m.parse_millis:10.1f} {m.decompress_millis:10.1f} {m.chunks:8d} "
+            f"{kib:7.1f} {ratio:6.2f}  {path.relative_to(replay_dir)}"
+        )
+
+
+@app.command(rich_help_panel="Library statistics")
+def quick_scan(
+    replay_dir: Annotated[
+        Optional[Path],
+        typer.Option(file_okay=False, dir_okay=True, exists=True, readable=True),
+    ] = None,
+):
+    """Count your replays by game build and month, without fully parsing them."""
+    from shroudstone.library import quick_scan
+
+    if replay_dir is None:
+        replay_dir = get_replay_dir(Config.load())
+    scan = quick_scan(replay_dir)
+    for title, groups in [("Build", scan.by_build), ("Month", scan.by_month)]:
+        typer.echo(f"{title:>10} {'replays':>8} {'size':>10}")
+        for key, group in sorted(groups.items()):
+            size = f"{group.total_bytes / 1e6:.1f} MB"
+            typer.echo(f"{key:>10} {group.replays:8d} {size:>10}")
+        typer.echo("")
+    if scan.unreadable:
+        typer.echo(f"{len(scan.unreadable)} replays had unreadable headers.")
+
+
+@app.command(rich_help_panel="Library statistics")
+def filter_replays(
+    replay_dir: Annotated[
+        Optional[Path],
+        typer.Option(file_okay=False, dir_okay=True, exists=True, readable=True),
+    ] = None,
+    min_build: Annotated[Optional[int], typer.Option()] = None,
+    max_build: Annotated[Optional[int], typer.Option()] = None,
+    min_bytes: Annotated[Optional[int], typer.Option()] = None,
+    max_bytes: Annotated[Optional[int], typer.Option()] = None,
+    modified_after: Annotated[Optional[datetime], typer.Option()] = None,
+    modified_before: Annotated[Optional[datetime], typer.Option()] = None,
+    map_name: Annotated[
+        Optional[List[str]], typer.Option("--map", help="Can be given several times")
+    ] = None,
+    match_type: Annotated[
+        Optional[List[str]],
+        typer.Option(help="1v1, coop or campaign; can be given several times"),
+    ] = None,
+):
+    """List the replays matching some criteria, without fully parsing them."""
+    from shroudstone.library import ReplayFilter, filter_replays
+    from shroudstone.replay import MATCH_TYPES
+
+    for name in match_type or []:
+        if name not in MATCH_TYPES:
+            raise typer.BadParameter(
+                f"match type must be one of {', '.join(MATCH_TYPES)}, not {name}"
+            )
+    if replay_dir is None:
+        replay_dir = get_replay_dir(Config.load())
+    spec = ReplayFilter(
+        min_build=min_build,
+        max_build=max_build,
+        min_bytes=min_bytes,
+        max_bytes=max_bytes,
+        modified_after=modified_after,
+        modified_before=modified_before,
+        maps=map_name or [],
+        match_types=match_type or [],
+    )
+    for path in filter_replays(sorted(replay_dir.glob("**/*.SGReplay")), spec):
+        typer.echo(str(path.relative_to(replay_dir)))
+
+
+@app.command(rich_help_panel="Library statistics")
+def nickname_history(
+    replay_dir: Annotated[
+        Optional[Path],
+        typer.Option(file_okay=False, dir_okay=True, exists=True, readable=True),
+    ] = None,
+    all_players: Annotated[
+        bool, typer.Option(help="Include players who only ever used one nickname")
+    ] = False,
+):
+    """List the nicknames each player in your replays has used over time."""
+    from shroudstone.library import load_library, nickname_history
+
+    config = Config.load()
+    if replay_dir is None:
+        replay_dir = get_replay_dir(config)
+    replays = load_library(
+        replay_dir,
+        my_uuids=config.my_uuids,
+        name_timezone=config.replay_name_timezone,
+    )
+    history = nickname_history(replays)
+    for uuid, uses in history.items():
+        if len(uses) < 2 and not all_players:
+            continue
+        typer.echo(str(uuid))
+        for use in uses:
+            typer.echo(
+                f"  {use.nickname}#{use.discriminator or '?'}: {use.games} games, "
+                f"{use.first_seen:%Y-%m-%d} to {use.last_seen:%Y-%m-%d}"
+            )
+
+
+@app.command(rich_help_panel="Library statistics")
+def opponent_stats(
+    replay_dir: Annotated[
+        Optional[Path],
+        typer.Option(file_okay=False, dir_okay=True, exists=True, readable=True),
+    ] = None,
+    nickname: Annotated[
+        Optional[str], typer.Option(help="Only show opponents with this nickname")
+    ] = None,
+):
+    """Show faction picks and map results for each opponent you've played in 1v1."""
+    from shroudstone.library import load_library, opponent_stats
+    from shroudstone.names import nicknames_equal
+
+    config = Config.load()
+    if replay_dir is None:
+        replay_dir = get_replay_dir(config)
+    replays = load_library(
+        replay_dir,
+        my_uuids=config.my_uuids,
+        name_timezone=config.replay_name_timezone,
+    )
+    stats = opponent_stats(replays)
+    for s in sorted(stats.values(), key=lambda s: -s.games):
+        if nickname is not None and not nicknames_equal(nickname, s.nickname):
+            continue
+        factions = ", ".join(
+            f"{s.faction_share(f):.0%} {f.capitalize()}"
+            for f in sorted(s.factions, key=lambda f: -s.factions[f])
+        )
+        typer.echo(f"{s.nickname} ({s.uuid}): {s.games} games - {factions}")
+        for map_name, record in s.maps.items():
+            typer.echo(
+                f"  {map_name}: {record.wins}W {record.losses}L {record.unknown}?"
+            )
+
+
+@app.command(rich_help_panel="Library statistics")
+def ladder_history(
+    snapshots: Annotated[
+        List[Path],
+        typer.Argument(
+            exists=True, dir_okay=False, help="Leaderboard snapshot JSON files"
+        ),
+    ],
+    replay_dir: Annotated[
+        Optional[Path],
+        typer.Option(file_okay=False, dir_okay=True, exists=True, readable=True),
+    ] = None,
+):
+    """Show the ladder league and rank of the players in each of your replays,
+    according to the given leaderboard snapshots."""
+    from shroudstone.ladder import Ladder, reconcile
+    from shroudstone.library import load_library
+
+    config = Config.load()
+    if replay_dir is None:
+        replay_dir = get_replay_dir(config)
+    replays = load_library(
+        replay_dir,
+        my_uuids=config.my_uuids,
+        name_timezone=config.replay_name_timezone,
+    )
+    standings = reconcile(replays, Ladder.from_files(snapshots))
+    for replay in sorted(replays, key=lambda r: r.time):
+        parts = []
+        for player, standing in zip(replay.summary.players, standings[replay.path]):
+            if standing is None:
+                parts.append(f"{player.nickname} (unranked)")
+            else:
+                entry = standing.entry
+                parts.append(
+                    f"{player.nickname} ({entry.league or '?'} #{entry.rank or '?'})"
+                )
+        typer.echo(f"{replay.time:%Y-%m-%d %H:%M}: {' vs '.join(parts)}")
+
+
+@app.command(rich_help_panel="Library statistics")
+def report(
+    replay_dir: Annotated[
+        Optional[Path],
+        typer.Option(file_okay=False, dir_okay=True, exists=True, readable=True),
+    ] = None,
+    format: Annotated[
+        str, typer.Option(help="Output format: markdown or html")
+    ] = "markdown",
+    output: Annotated[
+        Optional[Path], typer.Option(help="File to write the report to")
+    ] = None,
+    explicit_only: Annotated[
+        bool,
+        typer.Option(help="Treat results guessed from disconnect times as unknown"),
+    ] = False,
+):
+    """Generate a report on your 1v1 results: win rates, streaks, maps and opponents."""
+    from shroudstone.library import export_report, load_library_with_failures
+    from shroudstone.replay import ResultConfidence
+
+    if format not in ("markdown", "html"):
+        raise typer.BadParameter("format must be markdown or html")
+    config = Config.load()
+    if replay_dir is None:
+        replay_dir = get_replay_dir(config)
+    library = load_library_with_failures(
+        replay_dir,
+        my_uuids=config.my_uuids,
+        name_timezone=config.replay_name_timezone,
+    )
+    text = export_report(
+        library.replays,
+        format=format,  # type: ignore
+        min_confidence=(
+            ResultConfidence.explicit if explicit_only else ResultConfidence.inferred
+        ),
+    )
+    if output is None:
+        typer.echo(text)
+    else:
+        output.write_text(text, encoding="utf-8")
+        typer.echo(f"Wrote report to {output}.")
+    if library.failures:
+        typer.echo(
+            f"{len(library.failures)} replays could not be parsed and were left out:",
+            err=True,
+        )
+        for failure in library.failures:
+            typer.echo(f"  {failure.path}: {failure.error}", err=True)
+
+
+@app.command(rich_help_panel="Tools for nerds")
+def check_corpus(
+    corpus_dir: Annotated[
+        Path, typer.Argument(file_okay=False, dir_okay=True, exists=True)
+    ],
+    update: Annotated[
+        bool, typer.Option(help="Rewrite the golden files instead of comparing")
+    ] = False,
+):
+    """Check that replays in a sample corpus still parse the same as their golden JSON."""
+    from shroudstone.corpus import check_corpus
+
+    results = check_corpus(corpus_dir, update=update)
+    for result in results:
+        if result.status != "ok":
+            typer.echo(f"{result.status.upper()}: {result.path}")
+        if result.diff:
+            typer.echo(result.diff)
+    failures = [r for r in results if r.status in ("changed", "new", "error")]
+    typer.echo(f"{len(results)} replays checked, {len(failures)} problems.")
+    if failures:
+        raise typer.Exit(1)
+
+
+@app.command(rich_help_panel="Tools for nerds")
+def fuzz_simulator(
+    iterations: Annotated[
+        int, typer.Option(help="Number of replays to generate")
+    ] = 1000,
+    seed: Annotated[int, typer.Option(help="First random seed to use")] = 0,
+):
+    """Simulate randomly generated replays, reporting any that break the parser."""
+    from shroudstone.synthetic import fuzz_simulator
+
+    failures = fuzz_simulator(iterations, seed=seed)
+    for failure in failures:
+        typer.echo(f"Seed {failure.seed}:")
+        for problem in failure.problems:
+            typer.echo(f"  {problem}")
+    typer.echo(f"{iterations} replays simulated, {len(failures)} failures.")
+    if failures:
+        raise typer.Exit(1)
 
 
 @app.command(rich_help_panel="Tools for nerds")
@@ -161,6 +684,12 @@ def rename_replays(
     ] = None,
     backup: bool = True,
     dry_run: bool = False,
+    on_conflict: Annotated[
+        Optional[str],
+        typer.Option(
+            help="What to do if the new name is taken: skip, counter or overwrite"
+        ),
+    ] = None,
     reprocess: Annotated[
         bool, typer.Option(help="Reprocess old replays that have already been renamed")
     ] = False,
@@ -195,22 +724,116 @@ def rename_replays(
     """
     from shroudstone import renamer
 
+    if on_conflict not in (None, "skip", "counter", "overwrite"):
+        raise typer.BadParameter("on-conflict must be skip, counter or overwrite")
     config = Config.load()
     if replay_dir is None:
         replay_dir = get_replay_dir(config)
     if clear_cache:
         renamer.clear_cached_matches()
+    options = config.rename_options()
+    if format_1v1:
+        options["format_1v1"] = format_1v1
+    if format_generic:
+        options["format_generic"] = format_generic
+    if on_conflict:
+        options["on_conflict"] = on_conflict
     renamer.rename_replays(
         replay_dir=replay_dir,
         dry_run=dry_run,
         backup=backup,
         reprocess=reprocess,
-        format_1v1=format_1v1 or config.replay_name_format_1v1,
-        format_generic=format_generic or config.replay_name_format_generic,
+        **options,
     )
 
 
+@app.command(rich_help_panel="Replay renaming")
+def watch(
+    replay_dir: Annotated[
+        Optional[Path],
+        typer.Option(file_okay=False, dir_okay=True, exists=True, readable=True),
+    ] = None,
+    interval: Annotated[
+        float, typer.Option(help="Seconds to wait between checks for new replays")
+    ] = 30,
+    backup: Annotated[
+        bool, typer.Option(help="Back up your replays once at startup")
+    ] = True,
+):
+    """Keep running in the background, renaming new replays as they appear.
+
+    Stop it with Ctrl-C (or SIGTERM). To start it at login on Windows without
+    a console window, use [b]pythonw -m shroudstone watch[/b]."""
+    from shroudstone import renamer
+    from shroudstone.watcher import WATCHER_STATE_FILE, watch
+
+    config = Config.load()
+    if replay_dir is None:
+        replay_dir = get_replay_dir(config)
+
+    def rename(first_run: bool):
+        return renamer.rename_replays(
+            replay_dir=replay_dir,  # type: ignore
+            backup=backup and first_run,
+            **config.rename_options(),
+        )
+
+    logger.info(f"Recording watcher state in {WATCHER_STATE_FILE}.")
+    state = watch(rename, interval=interval)
+    typer.echo(f"Renamed {state.renamed} replays in {state.runs} runs.")
+
+
+@app.command(rich_help_panel="Replay renaming")
+def reorganize_replays(
+    scheme: Annotated[
+        str,
+        typer.Argument(
+            help="month, matchup, map, or a format string like '{time:%Y}/{map_name}'"
+        ),
+    ],
+    replay_dir: Annotated[
+        Optional[Path],
+        typer.Option(file_okay=False, dir_okay=True, exists=True, readable=True),
+    ] = None,
+    backup: bool = True,
+    dry_run: bool = False,
+):
+    """Move your replays into subfolders by month, matchup or map."""
+    from shroudstone import renamer
+    from shroudstone.organize import reorganize
+
+    config = Config.load()
+    if replay_dir is None:
+        replay_dir = get_replay_dir(config)
+    if backup and not dry_run:
+        renamer.backup_dir(replay_dir, replay_dir.parent / f"{replay_dir.name}.backup")
+    reorganize(
+        replay_dir,
+        scheme,
+        dry_run=dry_run,
+        my_uuids=config.my_uuids,
+        name_timezone=config.replay_name_timezone,
+    )
+
+
+@app.command(rich_help_panel="Replay renaming")
+def undo_renames(
+    last: Annotated[
+        Optional[int], typer.Option(help="Only undo this many of the most recent renames")
+    ] = None,
+):
+    """Put renamed (or reorganized) replays back where they were."""
+    from shroudstone import renamer
+
+    if last is None:
+        n = renamer.undo_all()
+    else:
+        n = renamer.undo_last(last)
+    typer.echo(f"Undid {n} renames.")
+
+
 def get_replay_dir(config: Config) -> Path:
+    from shroudstone.paths import normalize_replay_dir
     from shroudstone.renamer import guess_replay_dir
 
     if config.replay_dir is None:
@@ -224,4 +847,4 @@ def get_replay_dir(config: Config) -> Path:
             typer.echo("If you know the correct path, please enter it now.")
             config.replay_dir = Path(typer.prompt("Path to replay directory"))
         config.save()
-    return config.replay_dir
+    return normalize_replay_dir(config.replay_dir)
@@ -4,7 +4,9 @@ import os
 import platform
 import yaml
 from pathlib import Path
-from typing import Optional
+from typing import Any, Dict, List, Optional
+from uuid import UUID
+from typing_extensions import Literal
 
 from pydantic import BaseModel, ConfigDict
 
@@ -34,6 +36,46 @@ class Config(BaseModel):
     replay_name_format_generic: str = DEFAULT_GENERIC_FORMAT
     minimize_to_tray: bool = False
     show_log_on_autorename: bool = False
+    replay_metadata: Literal["none", "json", "xattr"] = "none"
+    my_uuids: List[UUID] = []
+    """Player UUIDs of all your accounts, so that games between your accounts
+    (or replays stored outside your replay folder) are named from your
+    perspective."""
+    language: str = "en"
+    """Language for faction/result names in filenames (en, de, fr or es)"""
+    display_names: Dict[str, str] = {}
+    """Custom faction/result names for filenames, e.g. {"win": "GG"}"""
+    on_conflict: Literal["skip", "counter", "overwrite"] = "skip"
+    """What to do when a replay's new name is already taken"""
+    long_paths: bool = False
+    """On Windows, use \\\\?\\ paths for names beyond MAX_PATH instead of
+    shortening them (requires the replay viewer to support long paths too)."""
+    replay_mtime: Literal["preserve", "game_time"] = "preserve"
+    """Whether renamed replays keep their modification time, or have it set to
+    when the game was played (which fixes the order of the in-game list)."""
+    transliterate_nicknames: bool = False
+    """Reduce nicknames in filenames to plain ASCII where possible (e.g. "Zoë"
+    becomes "Zoe")"""
+    replay_name_timezone: str = "utc"
+    """Timezone for times in filenames: utc, local, or a fixed offset like
+    +10:00. Keep it the same on every machine sharing a replay folder."""
+
+    def rename_options(self) -> Dict[str, Any]:
+        """Keyword arguments for `renamer.rename_replays` taken from this config,
+        so that every frontend renames replays the same way."""
+        return dict(
+            format_1v1=self.replay_name_format_1v1,
+            format_generic=self.replay_name_format_generic,
+            metadata=self.replay_metadata,
+            my_uuids=self.my_uuids,
+            language=self.language,
+            display_names=self.display_names,
+            on_conflict=self.on_conflict,
+            long_paths=self.long_paths,
+            mtime_mode=self.replay_mtime,
+            transliterate_nicknames=self.transliterate_nicknames,
+            name_timezone=self.replay_name_timezone,
+        )
 
     @staticmethod
     def load():
@@ -0,0 +1,75 @@
+"""Regression checks of the parser against a corpus of sample replays.
+
+Each replay in the corpus has a "golden" JSON file alongside it (e.g.
+foo.SGReplay.golden.json) recording the GameState we expect it to produce.
+Running the check re-parses every replay and reports any whose state has
+changed; in update mode the golden files are rewritten instead."""
+from __future__ import annotations
+import difflib
+import json
+import logging
+from pathlib import Path
+from typing import List, NamedTuple, Optional
+
+from shroudstone.replay import GameState
+
+logger = logging.getLogger(__name__)
+
+GOLDEN_SUFFIX = ".golden.json"
+
+
+class CorpusResult(NamedTuple):
+    path: Path
+    status: str
+    """One of ok, changed, new (no golden file yet), updated or error"""
+    diff: Optional[str] = None
+    """Unified diff of golden vs actual output for changed replays, or the
+    error message for replays that failed to parse"""
+
+
+def golden_path(replay: Path) -> Path:
+    return replay.parent / f"{replay.name}{GOLDEN_SUFFIX}"
+
+
+def serialize_state(state: GameState) -> str:
+    """Serialize a GameState in a stable form suitable for diffing."""
+    data = state.model_dump(mode="json")
+    return json.dumps(data, indent=2, sort_keys=True) + "\n"
+
+
+def check_replay(replay: Path, update: bool = False) -> CorpusResult:
+    """Compare the parse of a single replay against its golden output."""
+    try:
+        actual = serialize_state(GameState.at_end_of(replay))
+    except Exception as e:
+        logger.error(f"Failed to parse {replay}: {e}")
+        return CorpusResult(replay, "error", str(e))
+    golden = golden_path(replay)
+    if not golden.exists():
+        if update:
+            golden.write_text(actual, encoding="utf-8")
+            return CorpusResult(replay, "updated")
+        return CorpusResult(replay, "new")
+    expected = golden.read_text(encoding="utf-8")
+    if expected == actual:
+        return CorpusResult(replay, "ok")
+    if update:
+        golden.write_text(actual, encoding="utf-8")
+        return CorpusResult(replay, "updated")
+    diff = "".join(
+        difflib.unified_diff(
+            expected.splitlines(keepends=True),
+            actual.splitlines(keepends=True),
+            fromfile=str(golden),
+            tofile=f"{replay} (actual)",
+        )
+    )
+    return CorpusResult(replay, "changed", diff)
+
+
+def check_corpus(corpus_dir: Path, update: bool = False) -> List[CorpusResult]:
+    """Check every replay under a directory against its golden output."""
+    return [
+        check_replay(replay, update=update)
+        for replay in sorted(corpus_dir.glob("**/*.SGReplay"))
+    ]
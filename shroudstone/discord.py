@@ -0,0 +1,72 @@
+"""Format replay summaries as Discord embeds, ready for a bot to post"""
+from __future__ import annotations
+import json
+from typing import Any, Dict, Optional
+
+from shroudstone.replay import (
+    GameState,
+    Player,
+    ReplaySummary,
+    format_duration,
+    infer_result,
+    summarize_state,
+)
+
+EMBED_COLORS = {"win": 0x2ECC71, "loss": 0xE74C3C, None: 0x95A5A6}
+"""Sidebar colour for the embed, by result of the first player"""
+
+
+def player_result(info: ReplaySummary, player: Player) -> Optional[str]:
+    """Result ("win", "loss" or None) for a player, if this was a 1v1."""
+    if len(info.players) != 2:
+        return None
+    other = info.players[1] if player is info.players[0] else info.players[0]
+    return infer_result(player, other, info.build_number)
+
+
+def discord_embed(state: GameState) -> Dict[str, Any]:
+    """Describe a match as a Discord embed object (see the "Embed Object"
+    section of Discord's API docs)."""
+    info = summarize_state(state)
+    fields = []
+    for player in info.players:
+        faction = (player.faction or "unknown").capitalize()
+        result = player_result(info, player)
+        value = faction if result is None else f"{faction} - {result.capitalize()}"
+        if player.is_ai:
+            value += " (AI)"
+        fields.append({"name": player.nickname, "value": value, "inline": True})
+    fields.append({"name": "Map", "value": info.map_name or "Unknown", "inline": True})
+    if info.duration_seconds is not None:
+        fields.append(
+            {
+                "name": "Duration",
+                "value": format_duration(info.duration_seconds, "clock"),
+                "inline": True,
+            }
+        )
+    if info.spectators:
+        fields.append(
+            {
+                "name": "Spectators",
+                "value": ", ".join(s.nickname for s in info.spectators),
+                "inline": False,
+            }
+        )
+    title = " vs ".join(p.nickname for p in info.players) or "Stormgate match"
+    first_result = player_result(info, info.players[0]) if info.players else None
+    description = info.map_name or "Unknown map"
+    if info.matchup:
+        description = f"{info.matchup} on {description}"
+    return {
+        "title": title,
+        "description": description,
+        "color": EMBED_COLORS[first_result],
+        "fields": fields,
+        "footer": {"text": f"Build {info.build_number}"},
+    }
+
+
+def to_discord_embed_json(state: GameState) -> str:
+    """A JSON webhook payload containing the embed for a match."""
+    return json.dumps({"embeds": [discord_embed(state)]}, indent=2)
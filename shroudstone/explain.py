@@ -0,0 +1,135 @@
+"""Narrate a replay as a chronological, human-readable log of what happened
+in the lobby and the game - handy for debugging the simulation, and for
+casters wanting a quick rundown of a match."""
+from __future__ import annotations
+from typing import List, Optional
+
+from shroudstone.names import disambiguate_nicknames
+from shroudstone.replay import (
+    GameStarted,
+    GameState,
+    LeftGameReason,
+    LobbyVariable,
+    MapLoaded,
+    PlayerJoined,
+    PlayerLeft,
+    ReplayEvent,
+    ReplaySource,
+    SlotAssigned,
+    SlotChanged,
+    UnknownEvent,
+    VariableSet,
+    format_duration,
+    get_build_number,
+    iter_events,
+    lobby_variable,
+    seekable,
+)
+
+
+def client_name(state: GameState, client_id: int) -> str:
+    client = state.clients.get(client_id)
+    if client is None or not client.nickname:
+        return f"client {client_id}"
+    named = {i: c for i, c in state.clients.items() if c.nickname}
+    names = disambiguate_nicknames(
+        [c.nickname for c in named.values()],
+        [c.discriminator for c in named.values()],
+        [c.slot_number for c in named.values()],
+    )
+    return dict(zip(named, names))[client_id]
+
+
+def slot_name(state: GameState, slot_number: int) -> str:
+    """Who's in a slot, for describing changes made to it."""
+    slot = state.slots.get(slot_number)
+    if slot is not None and slot.ai_type is not None:
+        return f"{slot.ai_display_name} (slot {slot_number})"
+    if slot is not None and slot.client_id is not None:
+        return client_name(state, slot.client_id)
+    return f"slot {slot_number}"
+
+
+def narrate(
+    before: GameState, after: GameState, event: ReplayEvent
+) -> Optional[str]:
+    """Describe an event, given copies of the state before and after it was
+    applied. Returns None for events not worth mentioning (e.g. commands)."""
+    if isinstance(event, MapLoaded):
+        return f"Lobby created on {event.name or 'an unknown map'}"
+    if isinstance(event, SlotAssigned):
+        who = event.nickname or str(event.uuid)
+        return f"Matchmaking put {who} in slot {event.slot_number}"
+    if isinstance(event, PlayerJoined):
+        return f"{client_name(after, event.client_id)} joined"
+    if isinstance(event, SlotChanged):
+        client = after.clients.get(event.client_id)
+        if client is None:
+            return None
+        who = client_name(after, event.client_id)
+        if client.is_spectator:
+            return f"{who} became a spectator"
+        return f"{who} took slot {client.slot_number}"
+    if isinstance(event, VariableSet):
+        variable = lobby_variable(after.map_name, event.variable_id)
+        slot = after.slots.get(event.slot_number)
+        if slot is None:
+            return None
+        if variable == LobbyVariable.faction:
+            if len(after.faction_changes) == len(before.faction_changes):
+                return None
+            faction = slot.faction.name.capitalize()
+            return f"{slot_name(after, event.slot_number)} picked {faction}"
+        if variable in (LobbyVariable.slot_type, LobbyVariable.ai_type):
+            if slot.ai_type is not None:
+                return f"Slot {event.slot_number} set to {slot.ai_display_name}"
+        return None
+    if isinstance(event, GameStarted):
+        who = client_name(after, event.client_id)
+        if not before.game_started:
+            return f"{who} finished loading - game started"
+        return f"{who} finished loading"
+    if isinstance(event, PlayerLeft):
+        who = client_name(before, event.client_id)
+        if not before.game_started:
+            return f"{who} left the lobby"
+        if event.reason == LeftGameReason.surrender:
+            return f"{who} surrendered"
+        return f"{who} left the game"
+    return None
+
+
+def explain_replay(
+    replay: ReplaySource, gzip_offset: Optional[int] = None, recover: bool = False
+) -> str:
+    """Produce a narration of a replay, one line per event, each prefixed with
+    the time since the replay started (e.g. "12:10 Bar surrendered")."""
+    replay = seekable(replay)
+    build_number = get_build_number(replay, gzip_offset)
+    state = GameState(build_number=build_number)
+    lines: List[str] = []
+    start: Optional[int] = None
+    last = 0
+
+    def line(timestamp: int, text: str):
+        seconds = (timestamp - (start or 0)) / state.ticks_per_second
+        lines.append(f"{format_duration(seconds, 'clock'):>7} {text}")
+
+    for event in iter_events(replay, gzip_offset, recover):
+        if start is None:
+            start = event.timestamp
+        last = event.timestamp
+        if state.is_lobby_reset(event):
+            line(event.timestamp, "Lobby reused for another game")
+            state = GameState(build_number=build_number)
+        if isinstance(event, UnknownEvent):
+            # Commands make up most of a replay and aren't narrated, so skip
+            # copying the state for them
+            state.apply(event)
+            continue
+        before = state.model_copy(deep=True)
+        state.apply(event)
+        if (text := narrate(before, state, event)) is not None:
+            line(event.timestamp, text)
+    line(last, "Replay ends")
+    return "\n".join(lines)
@@ -387,10 +387,9 @@ def create_main_ui(root: App, cfg: config.Config):
             rename_replays_wrapper,
             callback,
             replay_dir=cfg.replay_dir,
-            format_1v1=cfg.replay_name_format_1v1,
-            format_generic=cfg.replay_name_format_generic,
             reprocess=state.reprocess.get(),
             dry_run=state.dry_run.get(),
+            **cfg.rename_options(),
         )
 
     options_frame = ttk.LabelFrame(root, text="Options")
@@ -0,0 +1,45 @@
+"""Localized display names for factions and game results used in filenames"""
+from typing import Dict, Mapping, Optional
+
+DISPLAY_NAMES: Dict[str, Dict[str, str]] = {
+    "en": {
+        "win": "Win",
+        "loss": "Loss",
+        "unknown": "Unknown",
+    },
+    "de": {
+        "win": "Sieg",
+        "loss": "Niederlage",
+        "unknown": "Unbekannt",
+    },
+    "fr": {
+        "win": "Victoire",
+        "loss": "Défaite",
+        "unknown": "Inconnu",
+    },
+    "es": {
+        "win": "Victoria",
+        "loss": "Derrota",
+        "unknown": "Desconocido",
+    },
+}
+"""Display names for game results (and optionally factions) in each language.
+
+Anything missing falls back to English, and then to the capitalized enum
+name - which is what we use for factions, since they're proper nouns."""
+
+
+def display_name(
+    key: Optional[str],
+    language: str = "en",
+    overrides: Optional[Mapping[str, str]] = None,
+) -> str:
+    """Look up the display name for a faction or result (e.g. "infernals" or
+    "win"), preferring user-configured overrides."""
+    key = (key or "unknown").lower()
+    if overrides and key in overrides:
+        return overrides[key]
+    for lang in (language, "en"):
+        if key in DISPLAY_NAMES.get(lang, {}):
+            return DISPLAY_NAMES[lang][key]
+    return key.capitalize()
@@ -0,0 +1,110 @@
+"""Join parsed replays to leaderboard snapshots, to see what league and rank
+each player was at when a game was played.
+
+Snapshots are JSON files, either an object like
+    {"time": "2024-08-01T00:00:00Z", "entries": [...]}
+or just the list of entries (in which case the file's modification time is
+used as the snapshot time). Each entry needs a nickname and discriminator and
+may have a league, rank and mmr."""
+from __future__ import annotations
+from bisect import bisect_right
+from datetime import datetime, timezone
+import json
+import logging
+from pathlib import Path
+from typing import Dict, Iterable, List, Optional, Tuple
+
+from pydantic import BaseModel
+
+from shroudstone.names import normalize_nickname
+from shroudstone.renamer import Replay
+
+logger = logging.getLogger(__name__)
+
+
+class LadderEntry(BaseModel):
+    nickname: str
+    discriminator: str
+    league: Optional[str] = None
+    rank: Optional[int] = None
+    mmr: Optional[float] = None
+
+
+class LadderSnapshot(BaseModel):
+    time: datetime
+    entries: List[LadderEntry] = []
+
+
+class LadderStanding(BaseModel):
+    """A player's ladder entry as of a particular game."""
+
+    entry: LadderEntry
+    snapshot_time: datetime
+    """When the snapshot this came from was taken - the closer to the game
+    time, the more trustworthy"""
+
+
+def _utc(time: datetime) -> datetime:
+    """Treat naive datetimes as UTC, as the rest of shroudstone does."""
+    return time if time.tzinfo is not None else time.replace(tzinfo=timezone.utc)
+
+
+def ladder_key(nickname: str, discriminator: Optional[str]) -> Tuple[str, str]:
+    return normalize_nickname(nickname).casefold(), discriminator or ""
+
+
+def load_snapshot(path: Path) -> LadderSnapshot:
+    data = json.loads(path.read_text(encoding="utf-8"))
+    if isinstance(data, list):
+        mtime = datetime.fromtimestamp(path.stat().st_mtime, tz=timezone.utc)
+        data = {"time": mtime, "entries": data}
+    return LadderSnapshot.model_validate(data)
+
+
+class Ladder:
+    """A series of leaderboard snapshots, indexed for lookup by player."""
+
+    def __init__(self, snapshots: Iterable[LadderSnapshot]):
+        self.snapshots = sorted(snapshots, key=lambda s: _utc(s.time))
+        self._times = [_utc(s.time) for s in self.snapshots]
+        self._indexes: List[Dict[Tuple[str, str], LadderEntry]] = [
+            {ladder_key(e.nickname, e.discriminator): e for e in s.entries}
+            for s in self.snapshots
+        ]
+
+    @classmethod
+    def from_files(cls, paths: Iterable[Path]) -> Ladder:
+        return cls(load_snapshot(path) for path in paths)
+
+    def standing(
+        self, nickname: str, discriminator: Optional[str], time: datetime
+    ) -> Optional[LadderStanding]:
+        """Find a player's entry in the latest snapshot taken at or before
+        `time`, falling back to the earliest one after it."""
+        if not self.snapshots:
+            return None
+        key = ladder_key(nickname, discriminator)
+        before = bisect_right(self._times, _utc(time))
+        for i in [*range(before - 1, -1, -1), *range(before, len(self.snapshots))]:
+            if (entry := self._indexes[i].get(key)) is not None:
+                return LadderStanding(entry=entry, snapshot_time=self._times[i])
+        return None
+
+
+def reconcile(
+    replays: Iterable[Replay], ladder: Ladder
+) -> Dict[Path, List[Optional[LadderStanding]]]:
+    """Look up the ladder standing of each player in each replay (in the same
+    order as `replay.summary.players`), or None for players that aren't on
+    the ladder (including AIs)."""
+    standings = {}
+    for replay in replays:
+        standings[replay.path] = [
+            None
+            if player.is_ai
+            else ladder.standing(
+                player.nickname, player.nickname_discriminator, replay.time
+            )
+            for player in replay.summary.players
+        ]
+    return standings
@@ -0,0 +1,618 @@
+"""Aggregate statistics across a whole library of replays"""
+from __future__ import annotations
+from datetime import datetime, timedelta
+import html
+import logging
+import os
+from pathlib import Path
+import sys
+from typing import (
+    Dict,
+    Iterable,
+    Iterator,
+    List,
+    NamedTuple,
+    Optional,
+    Sequence,
+    Tuple,
+    Union,
+)
+from typing_extensions import Literal
+from uuid import UUID
+
+from pydantic import BaseModel
+
+from shroudstone.names import disambiguate_nicknames
+from shroudstone.renamer import Replay, get_result, get_result_confidence
+from shroudstone.replay import (
+    PathLike,
+    Player,
+    ReplaySummary,
+    ResultConfidence,
+    as_path,
+    get_build_number,
+    infer_result,
+    match_type_info,
+    peek_map,
+)
+
+logger = logging.getLogger(__name__)
+
+
+def iter_library(
+    replay_dir: Path,
+    my_uuids: Sequence[UUID] = (),
+    intern_strings: bool = True,
+    name_timezone: str = "utc",
+) -> Iterator[Tuple[Path, Union[Replay, Exception]]]:
+    """Parse each replay in a directory (recursively) as it's asked for,
+    yielding (path, replay) pairs - or (path, exception) for replays that
+    failed to parse, so callers can report them. Only one replay is held in
+    memory at a time, unless the caller keeps them.
+
+    With `intern_strings`, repeated nicknames, map names etc. share a single
+    string object across all the replays, which adds up in large libraries.
+    `name_timezone` is the timezone replays were renamed with (see
+    `Replay.from_path`)."""
+    for path in sorted(replay_dir.glob("**/*.SGReplay")):
+        try:
+            replay = Replay.from_path(
+                path, my_uuids=my_uuids, name_timezone=name_timezone
+            )
+        except Exception as e:
+            yield path, e
+            continue
+        if replay is not None:
+            if intern_strings:
+                intern_summary(replay.summary)
+            yield path, replay
+
+
+class LoadFailure(NamedTuple):
+    path: Path
+    error: str
+
+
+class LoadedLibrary(NamedTuple):
+    replays: List[Replay]
+    failures: List[LoadFailure]
+    """Replays that couldn't be parsed, and why"""
+
+
+def load_library_with_failures(
+    replay_dir: Path,
+    my_uuids: Sequence[UUID] = (),
+    intern_strings: bool = True,
+    name_timezone: str = "utc",
+) -> LoadedLibrary:
+    """Parse every replay in a directory (recursively), keeping track of any
+    that fail (see `iter_library`)."""
+    library = LoadedLibrary(replays=[], failures=[])
+    for path, result in iter_library(
+        replay_dir, my_uuids, intern_strings, name_timezone
+    ):
+        if isinstance(result, Exception):
+            logger.warning(f"Could not parse {path}: {result}")
+            library.failures.append(LoadFailure(path, str(result) or repr(result)))
+        else:
+            library.replays.append(result)
+    return library
+
+
+def load_library(
+    replay_dir: Path,
+    my_uuids: Sequence[UUID] = (),
+    intern_strings: bool = True,
+    name_timezone: str = "utc",
+) -> List[Replay]:
+    """Parse every replay in a directory (recursively), skipping any that fail
+    (see `load_library_with_failures` to find out which)."""
+    return load_library_with_failures(
+        replay_dir, my_uuids, intern_strings, name_timezone
+    ).replays
+
+
+def _intern(value: Optional[str]) -> Optional[str]:
+    return None if value is None else sys.intern(value)
+
+
+def intern_summary(summary: ReplaySummary):
+    """Replace the strings in a replay summary with interned copies."""
+    summary.map_name = _intern(summary.map_name)
+    summary.matchup = sys.intern(summary.matchup)
+    for player in summary.players:
+        player.nickname = sys.intern(player.nickname)
+        player.nickname_discriminator = _intern(player.nickname_discriminator)
+        player.faction = _intern(player.faction)
+        player.leave_reason = sys.intern(player.leave_reason)
+    for spectator in summary.spectators:
+        spectator.nickname = sys.intern(spectator.nickname)
+        spectator.nickname_discriminator = _intern(spectator.nickname_discriminator)
+
+
+class ScanGroup(BaseModel):
+    """Replay count and total size for a group of replays."""
+
+    replays: int = 0
+    total_bytes: int = 0
+
+    def add(self, size: int):
+        self.replays += 1
+        self.total_bytes += size
+
+
+class QuickScan(BaseModel):
+    """Overview of a replay library, from headers and file stats alone."""
+
+    by_build: Dict[int, ScanGroup] = {}
+    by_month: Dict[str, ScanGroup] = {}
+    """Keyed by the month (YYYY-MM) of each replay's modification time"""
+    unreadable: List[Path] = []
+
+
+def quick_scan(replay_dir: Path) -> QuickScan:
+    """Summarize the replays in a directory (recursively) by build number and
+    month, reading only their headers - much faster than `load_library`."""
+    scan = QuickScan()
+    for path in sorted(replay_dir.glob("**/*.SGReplay")):
+        try:
+            build_number = get_build_number(path)
+            stat = path.stat()
+        except Exception as e:
+            logger.warning(f"Could not read header of {path}: {e}")
+            scan.unreadable.append(path)
+            continue
+        month = f"{datetime.fromtimestamp(stat.st_mtime):%Y-%m}"
+        scan.by_build.setdefault(build_number, ScanGroup()).add(stat.st_size)
+        scan.by_month.setdefault(month, ScanGroup()).add(stat.st_size)
+    return scan
+
+
+class ReplayFilter(BaseModel):
+    """Criteria for `filter_replays`. Criteria left unset match any replay."""
+
+    min_build: Optional[int] = None
+    max_build: Optional[int] = None
+    min_bytes: Optional[int] = None
+    max_bytes: Optional[int] = None
+    modified_after: Optional[datetime] = None
+    modified_before: Optional[datetime] = None
+    maps: List[str] = []
+    """Map names to accept (ignoring case)"""
+    match_types: List[str] = []
+    """Match type names to accept (see `MATCH_TYPES`), e.g. 1v1 or coop"""
+
+    def header_matches(self, build_number: int, stat: os.stat_result) -> bool:
+        """Check the criteria that only need the file's header and stats."""
+        return (
+            (self.min_build is None or build_number >= self.min_build)
+            and (self.max_build is None or build_number <= self.max_build)
+            and (self.min_bytes is None or stat.st_size >= self.min_bytes)
+            and (self.max_bytes is None or stat.st_size <= self.max_bytes)
+            and (
+                self.modified_after is None
+                or stat.st_mtime >= self.modified_after.timestamp()
+            )
+            and (
+                self.modified_before is None
+                or stat.st_mtime < self.modified_before.timestamp()
+            )
+        )
+
+    def map_matches(self, map_name: Optional[str], map_folder: Optional[str]) -> bool:
+        """Check the criteria that need the map details from the first chunks."""
+        maps = {m.casefold() for m in self.maps}
+        if maps and (map_name is None or map_name.casefold() not in maps):
+            return False
+        match_type = match_type_info(map_name, map_folder)
+        return not self.match_types or match_type.name in self.match_types
+
+
+def filter_replays(paths: Iterable[PathLike], spec: ReplayFilter) -> List[Path]:
+    """Pick out the replays matching `spec`, reading no more of each than it
+    takes to decide: just the header for build, size and modification time,
+    plus the first few chunks for map and match type. Replays that can't be
+    read are left out."""
+    matched = []
+    for path in map(as_path, paths):
+        try:
+            if not spec.header_matches(get_build_number(path), path.stat()):
+                continue
+            if spec.maps or spec.match_types:
+                map_loaded = peek_map(path)
+                name = map_loaded.name if map_loaded is not None else None
+                folder = map_loaded.folder if map_loaded is not None else None
+                if not spec.map_matches(name, folder):
+                    continue
+        except Exception as e:
+            logger.warning(f"Could not read {path}: {e}")
+            continue
+        matched.append(path)
+    return matched
+
+
+class NicknameUse(BaseModel):
+    """A nickname used by an account, and when we saw it in use."""
+
+    nickname: str
+    discriminator: Optional[str] = None
+    first_seen: datetime
+    last_seen: datetime
+    games: int = 0
+
+
+def nickname_history(replays: Iterable[Replay]) -> Dict[UUID, List[NicknameUse]]:
+    """Map each player UUID to every nickname it has played under, ordered by
+    when each nickname was first seen."""
+    history: Dict[UUID, Dict[tuple, NicknameUse]] = {}
+    for replay in replays:
+        for player in replay.summary.players:
+            if player.uuid is None:
+                continue
+            key = (player.nickname, player.nickname_discriminator)
+            uses = history.setdefault(player.uuid, {})
+            if (use := uses.get(key)) is None:
+                use = uses[key] = NicknameUse(
+                    nickname=player.nickname,
+                    discriminator=player.nickname_discriminator,
+                    first_seen=replay.time,
+                    last_seen=replay.time,
+                )
+            use.first_seen = min(use.first_seen, replay.time)
+            use.last_seen = max(use.last_seen, replay.time)
+            use.games += 1
+    return {
+        uuid: sorted(uses.values(), key=lambda u: u.first_seen)
+        for uuid, uses in history.items()
+    }
+
+
+class Record(BaseModel):
+    """Win/loss record over a set of games."""
+
+    wins: int = 0
+    losses: int = 0
+    unknown: int = 0
+
+    def add(self, result: Optional[str]):
+        if result == "win":
+            self.wins += 1
+        elif result == "loss":
+            self.losses += 1
+        else:
+            self.unknown += 1
+
+    @property
+    def win_rate(self) -> Optional[float]:
+        decided = self.wins + self.losses
+        return self.wins / decided if decided else None
+
+
+class OpponentStats(BaseModel):
+    """What we know about an opponent from our 1v1 games against them."""
+
+    uuid: UUID
+    nickname: str
+    """Most recent nickname seen for this opponent"""
+    games: int = 0
+    factions: Dict[str, int] = {}
+    """Number of games in which they played each faction"""
+    maps: Dict[str, Record] = {}
+    """Our record against them on each map"""
+    last_played: Optional[datetime] = None
+
+    def faction_share(self, faction: str) -> float:
+        """Fraction of games in which this opponent played the given faction."""
+        return self.factions.get(faction, 0) / self.games if self.games else 0.0
+
+
+def opponent_stats(replays: Iterable[Replay]) -> Dict[UUID, OpponentStats]:
+    """Aggregate faction picks and per-map results for each opponent we've
+    played in a 1v1."""
+    stats: Dict[UUID, OpponentStats] = {}
+    for replay in sorted(replays, key=lambda r: r.time):
+        them = replay.them
+        if replay.us is None or them is None or them.uuid is None:
+            continue
+        s = stats.setdefault(
+            them.uuid, OpponentStats(uuid=them.uuid, nickname=them.nickname)
+        )
+        s.nickname = them.nickname
+        s.games += 1
+        s.last_played = replay.time
+        faction = them.faction or "unknown"
+        s.factions[faction] = s.factions.get(faction, 0) + 1
+        map_name = replay.summary.map_name or "unknown"
+        s.maps.setdefault(map_name, Record()).add(get_result(replay))
+    return stats
+
+
+REMATCH_GAP = timedelta(minutes=10)
+"""Maximum time between one game ending and the next starting for the second
+to count as a rematch"""
+
+
+def lineup(replay: Replay) -> frozenset:
+    """The set of players in a game, by UUID where known."""
+    return frozenset(p.uuid or p.nickname for p in replay.summary.players)
+
+
+def game_end(replay: Replay) -> datetime:
+    return replay.time + timedelta(seconds=replay.summary.duration_seconds or 0)
+
+
+def find_rematches(
+    replays: Iterable[Replay],
+    max_gap: timedelta = REMATCH_GAP,
+    same_map: bool = True,
+) -> Dict[Path, Path]:
+    """Link each game that looks like a rematch to the game it followed: the
+    same players (and map, if `same_map`) starting shortly after it ended.
+
+    Returns a mapping from the rematch's path to the original's path. The
+    same game may appear in several recorders' folders; only the first copy
+    of each is considered."""
+    rematch_of: Dict[Path, Path] = {}
+    previous: Dict[frozenset, Replay] = {}
+    for replay in sorted(replays, key=lambda r: r.time):
+        if len(replay.summary.players) < 2:
+            continue
+        key = lineup(replay)
+        if (prev := previous.get(key)) is not None:
+            if prev.time == replay.time:
+                continue  # Another copy of the same game
+            if replay.time - game_end(prev) <= max_gap and (
+                not same_map or prev.summary.map_name == replay.summary.map_name
+            ):
+                rematch_of[replay.path] = prev.path
+        previous[key] = replay
+    return rematch_of
+
+
+def player_key(player: Player) -> str:
+    """Identify a player across games: by UUID where known, otherwise by
+    nickname and discriminator."""
+    if player.uuid is not None:
+        return str(player.uuid)
+    return f"{player.nickname}#{player.nickname_discriminator or ''}"
+
+
+class SeriesGame(BaseModel):
+    path: Path
+    time: datetime
+    map_name: Optional[str] = None
+    winner: Optional[str] = None
+    """Key of the winner (see `player_key`), if known"""
+
+
+class Series(BaseModel):
+    """A run of consecutive games between the same players (e.g. a Bo3).
+
+    Players are identified by `player_key`; use `names` to display them."""
+
+    players: List[str]
+    names: Dict[str, str] = {}
+    """Display name of each player, with any clashing nicknames told apart
+    (see `disambiguate_nicknames`)"""
+    games: List[SeriesGame] = []
+
+    @property
+    def score(self) -> Dict[str, int]:
+        """Number of games won by each player."""
+        score = {player: 0 for player in self.players}
+        for game in self.games:
+            if game.winner is not None:
+                score[game.winner] = score.get(game.winner, 0) + 1
+        return score
+
+    @property
+    def winner(self) -> Optional[str]:
+        """The player who won the most games, if anyone did."""
+        ranked = sorted(self.score.items(), key=lambda x: -x[1])
+        if not ranked or (len(ranked) > 1 and ranked[0][1] == ranked[1][1]):
+            return None
+        return ranked[0][0]
+
+    def name(self, player: str) -> str:
+        """Display name for a player key."""
+        return self.names.get(player, player)
+
+
+def game_winner(replay: Replay) -> Optional[str]:
+    """Key (see `player_key`) of the winner of a 1v1, if known."""
+    if len(replay.summary.players) != 2:
+        return None
+    p1, p2 = replay.summary.players
+    result = infer_result(p1, p2, replay.summary.build_number)
+    if result is None:
+        return None
+    return player_key(p1 if result == "win" else p2)
+
+
+def group_series(
+    replays: Iterable[Replay], max_gap: timedelta = REMATCH_GAP
+) -> List[Series]:
+    """Group games into series of back-to-back games between the same
+    players (see `find_rematches`), with the winner of each game. Games that
+    weren't part of a run of two or more are left out."""
+    replays = list(replays)
+    by_path = {replay.path: replay for replay in replays}
+    rematch_of = find_rematches(replays, max_gap=max_gap, same_map=False)
+    series_of: Dict[Path, Series] = {}
+    for path, original in sorted(rematch_of.items(), key=lambda x: by_path[x[0]].time):
+        if (series := series_of.get(original)) is None:
+            first = by_path[original]
+            players = first.summary.players
+            names = disambiguate_nicknames(
+                [p.nickname for p in players],
+                [p.nickname_discriminator for p in players],
+                [p.slot_number for p in players],
+            )
+            series = series_of[original] = Series(
+                players=[player_key(p) for p in players],
+                names={player_key(p): name for p, name in zip(players, names)},
+            )
+            series.games.append(series_game(first))
+        series.games.append(series_game(by_path[path]))
+        series_of[path] = series
+    unique = {id(series): series for series in series_of.values()}
+    return sorted(unique.values(), key=lambda s: s.games[0].time)
+
+
+def series_game(replay: Replay) -> SeriesGame:
+    return SeriesGame(
+        path=replay.path,
+        time=replay.time,
+        map_name=replay.summary.map_name,
+        winner=game_winner(replay),
+    )
+
+
+SESSION_GAP = timedelta(hours=1)
+"""Games further apart than this are considered separate play sessions"""
+
+
+class LibraryReport(BaseModel):
+    """Overview of our 1v1 results across a replay library."""
+
+    games: int = 0
+    overall: Record = Record()
+    by_month: Dict[str, Record] = {}
+    longest_win_streak: int = 0
+    longest_loss_streak: int = 0
+    sessions: int = 0
+    maps: Dict[str, Record] = {}
+    opponents: Dict[str, Record] = {}
+
+
+def library_report(
+    replays: Iterable[Replay],
+    min_confidence: ResultConfidence = ResultConfidence.inferred,
+) -> LibraryReport:
+    """Compute win rate trends, streaks, sessions and most-played maps and
+    opponents for our 1v1 games.
+
+    Results we're less sure of than `min_confidence` count as unknown."""
+    report = LibraryReport()
+    streak_result = None
+    streak = 0
+    last_time: Optional[datetime] = None
+    for replay in sorted(replays, key=lambda r: r.time):
+        if replay.us is None or replay.them is None:
+            continue
+        result = get_result(replay)
+        if not get_result_confidence(replay).at_least(min_confidence):
+            result = None
+        report.games += 1
+        report.overall.add(result)
+        report.by_month.setdefault(f"{replay.time:%Y-%m}", Record()).add(result)
+        report.maps.setdefault(replay.summary.map_name or "unknown", Record()).add(result)
+        report.opponents.setdefault(replay.them.nickname, Record()).add(result)
+
+        if last_time is None or replay.time - last_time > SESSION_GAP:
+            report.sessions += 1
+        last_time = replay.time
+
+        if result is None:
+            continue
+        streak = streak + 1 if result == streak_result else 1
+        streak_result = result
+        if result == "win":
+            report.longest_win_streak = max(report.longest_win_streak, streak)
+        else:
+            report.longest_loss_streak = max(report.longest_loss_streak, streak)
+    return report
+
+
+def _most_played(records: Dict[str, Record], n: int = 10) -> List[tuple]:
+    def games(r: Record):
+        return r.wins + r.losses + r.unknown
+
+    return sorted(records.items(), key=lambda x: -games(x[1]))[:n]
+
+
+def _pct(rate: Optional[float]) -> str:
+    return "-" if rate is None else f"{rate:.0%}"
+
+
+def export_report(
+    replays: Iterable[Replay],
+    format: Literal["markdown", "html"] = "markdown",
+    min_confidence: ResultConfidence = ResultConfidence.inferred,
+) -> str:
+    """Render a human-readable report on a replay library."""
+    report = library_report(replays, min_confidence=min_confidence)
+    sections: List[tuple] = [
+        (
+            "Summary",
+            ["", "Value"],
+            [
+                ["Games", report.games],
+                ["Win rate", _pct(report.overall.win_rate)],
+                ["Record", f"{report.overall.wins}W {report.overall.losses}L"],
+                ["Longest win streak", report.longest_win_streak],
+                ["Longest loss streak", report.longest_loss_streak],
+                ["Play sessions", report.sessions],
+            ],
+        ),
+        (
+            "By month",
+            ["Month", "Games", "Win rate"],
+            [
+                [month, r.wins + r.losses + r.unknown, _pct(r.win_rate)]
+                for month, r in sorted(report.by_month.items())
+            ],
+        ),
+        (
+            "Most played maps",
+            ["Map", "Games", "Win rate"],
+            [
+                [name, r.wins + r.losses + r.unknown, _pct(r.win_rate)]
+                for name, r in _most_played(report.maps)
+            ],
+        ),
+        (
+            "Most played opponents",
+            ["Opponent", "Games", "Win rate"],
+            [
+                [name, r.wins + r.losses + r.unknown, _pct(r.win_rate)]
+                for name, r in _most_played(report.opponents)
+            ],
+        ),
+    ]
+    if format == "html":
+        return _render_html(sections)
+    return _render_markdown(sections)
+
+
+def _render_markdown(sections: List[tuple]) -> str:
+    lines = ["# Shroudstone replay report"]
+    for title, header, rows in sections:
+        lines += ["", f"## {title}", ""]
+        lines.append("| " + " | ".join(header) + " |")
+        lines.append("|" + "---|" * len(header))
+        for row in rows:
+            lines.append("| " + " | ".join(str(x) for x in row) + " |")
+    return "\n".join(lines) + "\n"
+
+
+def _render_html(sections: List[tuple]) -> str:
+    parts = [
+        "<!DOCTYPE html>",
+        '<html><head><meta charset="utf-8"><title>Shroudstone replay report</title></head><body>',
+        "<h1>Shroudstone replay report</h1>",
+    ]
+    for title, header, rows in sections:
+        parts.append(f"<h2>{html.escape(title)}</h2><table>")
+        parts.append(
+            "<tr>" + "".join(f"<th>{html.escape(h)}</th>" for h in header) + "</tr>"
+        )
+        for row in rows:
+            parts.append(
+                "<tr>"
+                + "".join(f"<td>{html.escape(str(x))}</td>" for x in row)
+                + "</tr>"
+            )
+        parts.append("</table>")
+    parts.append("</body></html>")
+    return "\n".join(parts) + "\n"
@@ -0,0 +1,59 @@
+"""Helpers for cleaning up player nicknames before we use them in filenames"""
+from collections import Counter
+import re
+from typing import List, Optional, Sequence
+import unicodedata
+
+ZERO_WIDTH_CHARS = re.compile("[\u200b-\u200f\u2060-\u2064\ufeff\u180e]")
+"""Invisible characters that some players use to decorate their names"""
+
+
+def normalize_nickname(nickname: str, transliterate: bool = False) -> str:
+    """Put a nickname into a canonical form suitable for display and filenames.
+
+    This NFC-normalizes the name and strips invisible/control characters. If
+    `transliterate` is set, we additionally try to reduce the name to plain
+    ASCII (e.g. "Ｐｏｘ" -> "Pox", "Zoë" -> "Zoe"), falling back to the
+    untransliterated name if nothing would be left."""
+    nickname = unicodedata.normalize("NFC", nickname)
+    nickname = ZERO_WIDTH_CHARS.sub("", nickname)
+    nickname = "".join(
+        c for c in nickname if unicodedata.category(c) not in ("Cc", "Cf")
+    ).strip()
+    if transliterate:
+        decomposed = unicodedata.normalize("NFKD", nickname)
+        ascii_name = decomposed.encode("ascii", "ignore").decode("ascii").strip()
+        if ascii_name:
+            nickname = ascii_name
+    return nickname
+
+
+def nicknames_equal(a: str, b: str) -> bool:
+    """Compare two nicknames, ignoring case, invisible characters and
+    differences in unicode representation."""
+    return normalize_nickname(a).casefold() == normalize_nickname(b).casefold()
+
+
+def disambiguate_nicknames(
+    nicknames: Sequence[str],
+    discriminators: Sequence[Optional[str]],
+    slot_numbers: Sequence[Optional[int]],
+) -> List[str]:
+    """Tell apart players whose nicknames are equal (see `nicknames_equal`) by
+    adding their discriminators (e.g. "Pox#1234"), or if those don't settle it,
+    their slot numbers (e.g. "Pox (slot 2)"). Unique nicknames are unchanged."""
+    keys = [normalize_nickname(n).casefold() for n in nicknames]
+    names = list(nicknames)
+    for key, count in Counter(keys).items():
+        if count < 2:
+            continue
+        clashing = [i for i, k in enumerate(keys) if k == key]
+        tags = [discriminators[i] for i in clashing]
+        if all(tags) and len(set(tags)) == len(tags):
+            for i in clashing:
+                names[i] = f"{nicknames[i]}#{discriminators[i]}"
+            continue
+        for n, i in enumerate(clashing, 1):
+            slot = slot_numbers[i]
+            names[i] = f"{nicknames[i]} ({n if slot is None else f'slot {slot}'})"
+    return names
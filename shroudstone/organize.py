@@ -0,0 +1,98 @@
+"""Move renamed replays into subfolders (by month, matchup, map, ...)"""
+from __future__ import annotations
+import logging
+from pathlib import Path
+from typing import Dict, Optional, Sequence
+from uuid import UUID
+
+from shroudstone.renamer import (
+    Replay,
+    move_replay_file,
+    sanitize_filename,
+    uuid_from_path,
+)
+
+logger = logging.getLogger(__name__)
+
+SCHEMES: Dict[str, str] = {
+    "month": "{time:%Y-%m}",
+    "matchup": "{matchup}",
+    "map": "{map_name}",
+}
+"""Named shortcuts for common folder templates"""
+
+
+def matchup(replay: Replay) -> str:
+    """Matchup string for a replay, e.g. "VvI" (from our perspective if we
+    played in it, otherwise in canonical order), or "3 players" for games
+    that aren't 1v1."""
+    if replay.us and replay.them:
+        players = [replay.us, replay.them]
+        return "v".join(f"{(p.faction or '?').upper():.1}" for p in players)
+    if len(replay.summary.players) != 2:
+        return f"{len(replay.summary.players)} players"
+    return replay.summary.matchup
+
+
+def account_root(path: Path, replay_dir: Path) -> Path:
+    """The per-account folder (Replays/<uuid>) a replay lives under, or the
+    replay directory itself if it isn't in one."""
+    for parent in path.parents:
+        if parent == replay_dir:
+            break
+        if uuid_from_path(Path(parent.name)) is not None:
+            return parent
+    return replay_dir
+
+
+def reorganize(
+    replay_dir: Path,
+    scheme: str,
+    dry_run: bool = False,
+    my_uuids: Sequence[UUID] = (),
+    name_timezone: str = "utc",
+):
+    """Move every replay under `replay_dir` into subfolders given by `scheme`,
+    which is either the name of one of SCHEMES or a format string using the
+    fields time, map_name, build_number and matchup (nested folders can be
+    made with "/"). Metadata sidecar files are moved along with their replays."""
+    template = SCHEMES.get(scheme, scheme)
+    moved = 0
+    for path in sorted(replay_dir.glob("**/*.SGReplay")):
+        try:
+            replay = Replay.from_path(
+                path, my_uuids=my_uuids, name_timezone=name_timezone
+            )
+        except Exception as e:
+            logger.error(f"Could not parse {path}, leaving it alone: {e}")
+            continue
+        if replay is None:
+            continue
+        subfolder = template.format(
+            time=replay.time,
+            map_name=replay.summary.map_name or "Unknown map",
+            build_number=replay.summary.build_number,
+            matchup=matchup(replay),
+        )
+        parts = [sanitize_filename(p).strip() or "_" for p in subfolder.split("/")]
+        target = account_root(path, replay_dir).joinpath(*parts) / path.name
+        if move_replay(path, target, dry_run=dry_run) is not None:
+            moved += 1
+    prefix = "DRY RUN: " if dry_run else ""
+    logger.info(f"{prefix}{moved} replays moved.")
+
+
+def move_replay(source: Path, target: Path, dry_run: bool) -> Optional[Path]:
+    """Move a replay (and its sidecar, if any) to a new location."""
+    if source == target:
+        return None
+    if target.exists():
+        logger.error(f"Not moving {source}! {target} already exists!")
+        return None
+    if dry_run:
+        logger.info(f"DRY RUN: Would have moved {source} => {target}.")
+        return target
+    logger.info(f"Moving {source} => {target}.")
+    target.parent.mkdir(parents=True, exist_ok=True)
+    move_replay_file(source, target)
+    return target
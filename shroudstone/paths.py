@@ -0,0 +1,178 @@
+"""Discovery of Stormgate replay directories on the various platforms we support"""
+import logging
+import os
+import platform
+import re
+from pathlib import Path
+from typing import List, Optional
+
+logger = logging.getLogger(__name__)
+
+STORMGATE_STEAM_APP_ID = 2012510
+"""Steam app ID of Stormgate, used to find its Proton prefix"""
+
+REPLAY_DIR_TAIL = "AppData/Local/Stormgate/Saved/Replays"
+"""Location of the replay directory relative to a Windows user profile"""
+
+WINDOWS_PATH_PATTERN = re.compile(r"^([A-Za-z]):[\\/](.*)$")
+"""Matches absolute Windows paths like C:\\Users\\... or c:/users/..."""
+
+
+def find_replay_dirs() -> List[Path]:
+    """Find all Stormgate replay directories on this machine, most likely first."""
+    if platform.system() == "Windows":
+        # Should be easy, just look in the current user's local app data
+        appdata = os.environ["LOCALAPPDATA"]
+        candidates = [Path(appdata) / "Stormgate" / "Saved" / "Replays"]
+    else:
+        # If this script is running on Linux and Stormgate is installed using
+        # Steam+Proton, look in the steam compatdata:
+        candidates = [
+            path
+            for library in steam_library_dirs()
+            for path in (
+                library / f"steamapps/compatdata/{STORMGATE_STEAM_APP_ID}/pfx/dosdevices"
+            ).glob(f"*/users/steamuser/{REPLAY_DIR_TAIL}")
+        ]
+
+        # If this script is running on the Windows Subsystem for Linux, we can
+        # find the Windows drives mounted in /mnt:
+        wslmnt = Path("/mnt")
+        candidates += [
+            *wslmnt.glob(f"*/Users/*/{REPLAY_DIR_TAIL}"),
+            *wslmnt.glob(f"*/Documents and Settings/*/{REPLAY_DIR_TAIL}"),
+        ]
+
+    result: List[Path] = []
+    for path in candidates:
+        if path.is_dir() and path not in result:
+            result.append(path)
+    return result
+
+
+def steam_library_dirs() -> List[Path]:
+    """Find Steam library folders on Linux, including any custom library
+    locations listed in Steam's libraryfolders.vdf."""
+    roots = [
+        Path.home() / ".steam/root",
+        Path.home() / ".local/share/Steam",
+        # Flatpak install:
+        Path.home() / ".var/app/com.valvesoftware.Steam/.local/share/Steam",
+    ]
+    libraries: List[Path] = []
+    for root in roots:
+        if not root.is_dir():
+            continue
+        libraries.append(root)
+        vdf = root / "steamapps/libraryfolders.vdf"
+        if vdf.exists():
+            text = vdf.read_text(encoding="utf-8", errors="replace")
+            libraries.extend(Path(p) for p in re.findall(r'"path"\s+"([^"]+)"', text))
+    # Resolve symlinks (e.g. ~/.steam/root) so we don't search a library twice:
+    unique: List[Path] = []
+    for library in libraries:
+        library = library.resolve()
+        if library not in unique:
+            unique.append(library)
+    return unique
+
+
+def proton_prefixes() -> List[Path]:
+    """Find the Proton prefixes (Wine "C drives") Stormgate is installed in."""
+    return [
+        prefix
+        for library in steam_library_dirs()
+        if (
+            prefix := library / f"steamapps/compatdata/{STORMGATE_STEAM_APP_ID}/pfx"
+        ).is_dir()
+    ]
+
+
+def find_proton_prefix(path: Path) -> Optional[Path]:
+    """The Proton/Wine prefix containing a path, if any."""
+    for parent in [path, *path.parents]:
+        if (parent / "dosdevices").is_dir():
+            return parent
+    return None
+
+
+def windows_to_unix(windows_path: str, prefix: Path) -> Optional[Path]:
+    """Map a Windows path as seen by the game (e.g.
+    c:/users/steamuser/AppData/...) to where it lives inside a Proton
+    prefix, or None if it isn't an absolute Windows path. Path components
+    that don't exist with the given case are matched case-insensitively."""
+    if (m := WINDOWS_PATH_PATTERN.match(windows_path)) is None:
+        return None
+    drive, rest = m.groups()
+    path = prefix / "dosdevices" / f"{drive.lower()}:"
+    for part in rest.replace("\\", "/").split("/"):
+        if not part:
+            continue
+        # Windows paths are case-insensitive, but the files inside the prefix
+        # aren't, so follow whatever case is actually on disk:
+        if not (path / part).exists() and path.is_dir():
+            matches = [c for c in path.iterdir() if c.name.lower() == part.lower()]
+            if matches:
+                part = matches[0].name
+        path = path / part
+    return path
+
+
+def unix_to_windows(path: Path, prefix: Path) -> Optional[str]:
+    """Map a path inside a Proton prefix to the Windows path the game sees it
+    as, or None if no drive in the prefix contains it."""
+    real = path.resolve()
+    best = None
+    for device in (prefix / "dosdevices").iterdir():
+        if not re.fullmatch(r"[a-z]:", device.name):
+            continue
+        target = device.resolve()
+        # Prefer the most specific drive (z: is usually mapped to / and so
+        # contains everything):
+        if _is_relative_to(real, target) and (
+            best is None or len(target.parts) > len(best[1].parts)
+        ):
+            best = (device.name, target)
+    if best is None:
+        return None
+    drive, target = best
+    return f"{drive.upper()}/{real.relative_to(target).as_posix()}"
+
+
+def _is_relative_to(path: Path, other: Path) -> bool:
+    # (Path.is_relative_to needs python 3.9)
+    try:
+        path.relative_to(other)
+    except ValueError:
+        return False
+    return True
+
+
+def normalize_replay_dir(path: Path) -> Path:
+    """Turn a user-supplied replay directory into a real Unix path when
+    running under Linux against a Proton prefix.
+
+    Windows-form paths (e.g. copied from the game) are mapped into the prefix,
+    and paths through the dosdevices symlinks are resolved to where the files
+    actually live, so renames don't go astray across the symlink layer. Paths
+    inside a prefix are also checked to be visible to the game as a Windows
+    path. On Windows, or if nothing matches, the path is returned unchanged."""
+    if platform.system() == "Windows":
+        return path
+    if WINDOWS_PATH_PATTERN.match(str(path)):
+        for prefix in proton_prefixes():
+            candidate = windows_to_unix(str(path), prefix)
+            if candidate is not None and candidate.is_dir():
+                return candidate.resolve()
+        return path
+    if (prefix := find_proton_prefix(path)) is not None and path.is_dir():
+        windows_path = unix_to_windows(path, prefix)
+        if windows_path is None:
+            logger.warning(
+                f"{path} isn't on any drive of the Proton prefix {prefix}, so "
+                "the game won't see it"
+            )
+        else:
+            logger.debug(f"{path} is {windows_path} as seen by the game")
+        return path.resolve()
+    return path
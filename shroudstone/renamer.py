@@ -1,22 +1,50 @@
 """Rename stormgate replays to include useful info in filename"""
 from __future__ import annotations
 from collections import defaultdict
-from datetime import datetime, timedelta, timezone
+from datetime import datetime, timedelta, timezone, tzinfo
+import errno
 import os
+import platform
 import string
 from pathlib import Path
-import platform
 import re
 import logging
-from shutil import copytree, rmtree
-from typing import Iterable, NamedTuple, Optional, Union
+from shutil import copyfileobj, copystat, copytree, rmtree
+from typing import (
+    Dict,
+    Iterable,
+    List,
+    Mapping,
+    NamedTuple,
+    Optional,
+    Sequence,
+    Set,
+    Tuple,
+    Union,
+)
 from typing_extensions import Literal
 from uuid import UUID
 from packaging import version
+from pydantic import BaseModel
 
 from shroudstone import __version__
-from shroudstone.replay import Player, ReplaySummary, summarize_replay, FRIGATE
+from shroudstone.replay import (
+    INDEX_SUFFIX,
+    PathLike,
+    Player,
+    ReplaySummary,
+    ResultConfidence,
+    as_path,
+    format_duration,
+    infer_result,
+    judge_result,
+    summarize_replay,
+    uuid_from_path,
+)
 from shroudstone.config import data_dir
+from shroudstone.i18n import display_name
+from shroudstone.names import disambiguate_nicknames, normalize_nickname
+from shroudstone.paths import find_replay_dirs, normalize_replay_dir
 
 logger = logging.getLogger(__name__)
 
@@ -27,6 +55,21 @@ TOLERANCE = timedelta(seconds=90)
 BAD_CHARS = re.compile(r'[<>:"/\\|?*\0]')
 """Characters forbidden in filenames on Linux or Windows"""
 
+WINDOWS_RESERVED_NAMES = {
+    "CON", "PRN", "AUX", "NUL",
+    *(f"COM{i}" for i in range(1, 10)),
+    *(f"LPT{i}" for i in range(1, 10)),
+}
+"""Device names that can't be used as filenames (with any extension) on Windows"""
+
+MAX_PATH = 260
+"""Maximum path length supported by most Windows APIs"""
+
+TIMEZONE_OFFSET_PATTERN = re.compile(
+    r"(?:UTC|GMT)?([+-])(\d{1,2})(?::?(\d\d))?", re.IGNORECASE
+)
+"""Fixed UTC offsets accepted by `parse_timezone`, e.g. +10:00, -0530 or UTC+2"""
+
 cache_dir = data_dir / "stormgateworld-cache"
 """Directory in which match data is cached"""
 
@@ -36,6 +79,25 @@ uuid_dir = cache_dir / "by_uuid"
 skipped_replays_file = data_dir / "skipped_replays.txt"
 """Directory in which previouslyskipped replays are recorded"""
 
+MetadataMode = Literal["none", "json", "xattr"]
+"""Where to store parsed replay summaries after renaming"""
+
+SIDECAR_SUFFIX = ".json"
+"""Suffix appended to replay filenames to get their metadata sidecar file"""
+
+XATTR_NAME = "user.shroudstone.summary"
+"""Extended attribute in which replay metadata is stored in xattr mode"""
+
+ConflictStrategy = Literal["skip", "counter", "overwrite"]
+"""What to do when a replay's new name is already taken"""
+
+MtimeMode = Literal["preserve", "game_time"]
+"""What modification time renamed replays should have: their original one,
+or the time the game was played"""
+
+journal_file = data_dir / "rename_journal.jsonl"
+"""Append-only log of renames performed, used to undo them"""
+
 
 VALID_FIELDS = {
     "1v1": [
@@ -50,6 +112,7 @@ VALID_FIELDS = {
         "result",
         "map_name",
         "build_number",
+        "mod",
     ],
     "generic": [
         "players",
@@ -58,6 +121,7 @@ VALID_FIELDS = {
         "duration",
         "map_name",
         "build_number",
+        "mod",
     ]
 }
 
@@ -87,8 +151,27 @@ def rename_replays(
     backup: bool = True,
     reprocess: bool = False,
     files: Optional[Iterable[Path]] = None,
-):
+    metadata: MetadataMode = "none",
+    my_uuids: Sequence[UUID] = (),
+    language: str = "en",
+    display_names: Optional[Mapping[str, str]] = None,
+    on_conflict: ConflictStrategy = "skip",
+    long_paths: bool = False,
+    mtime_mode: MtimeMode = "preserve",
+    transliterate_nicknames: bool = False,
+    name_timezone: str = "utc",
+) -> Dict[str, int]:
+    """Rename all new (or with `reprocess`, all) replays in a directory.
+    Returns counts of replays renamed, skipped, etc.
+
+    Times in the new names are given in `name_timezone` (see
+    `parse_timezone`); renamed replays being reprocessed must have been named
+    with the same setting, or their times will be misread."""
+    parse_timezone(name_timezone)  # Fail early if it's invalid
     migrate()
+    if (normalized := normalize_replay_dir(replay_dir)) != replay_dir:
+        logger.info(f"Using {normalized} for replay directory {replay_dir}.")
+        replay_dir = normalized
     if dry_run:
         # Don't bother
         bu_dir = None
@@ -116,7 +199,9 @@ def rename_replays(
 
     def try_parse(path: Path):
         try:
-            return Replay.from_path(path)
+            return Replay.from_path(
+                path, my_uuids=my_uuids, name_timezone=name_timezone
+            )
         except Exception:
             logger.exception(f"Unexpected error parsing {path}")
 
@@ -126,7 +211,7 @@ def rename_replays(
             "No new replays found to rename! "
             f"If you weren't expecting this, check your replay_dir '{replay_dir}' is correct."
         )
-        return
+        return {}
 
     n = len(replays)
     earliest_time = min(x.time for x in replays)
@@ -138,47 +223,84 @@ def rename_replays(
         for x in skipped_replays_file.read_text(encoding="utf-8").splitlines()
         if x
     }
-    skipped_paths = []
 
     counts = defaultdict(lambda: 0)
+    # Targets already used by this run, so that two replays wanting the same
+    # name are detected even in a dry run:
+    claimed: Set[Path] = set()
+    plan: List[PlannedRename] = []
     for replay in replays:
         if replay.path in previously_skipped_paths:
             counts["skipped_old"] += 1
             logger.debug(
                 f"We've previously skipped {replay.path.name}, so not commenting on it this time."
             )
-        elif any(p.is_ai for p in replay.summary.players):
-                counts["skipped_new"] += 1
-                skipped_paths.append(replay.path)
-                logger.info(f"{replay.path.name} is a game vs AI, skipping it.")
-                continue
+            continue
+        try:
+            step = plan_rename(
+                replay,
+                format_1v1=format_1v1,
+                format_generic=format_generic,
+                language=language,
+                display_names=display_names,
+                on_conflict=on_conflict,
+                claimed=claimed,
+                long_paths=long_paths,
+                mtime_mode=mtime_mode,
+                transliterate_nicknames=transliterate_nicknames,
+                name_timezone=name_timezone,
+            )
+        except Exception as e:
+            logger.error(f"Unexpected error handling {replay.path}: {e}")
+            counts["error"] += 1
+            continue
+        if step is None:
+            counts["skipped_conflict"] += 1
         else:
-            try:
-                rename_replay(
-                    replay,
-                    dry_run=dry_run,
-                    format_1v1=format_1v1,
-                    format_generic=format_generic,
+            plan.append(step)
+
+    problems = validate_plan(plan)
+    for problem in problems:
+        logger.error(problem)
+    if problems:
+        logger.error("Not renaming anything, as the rename plan has problems.")
+        counts["error"] += len(problems)
+    elif dry_run:
+        for step in plan:
+            if step.target != step.replay.path:
+                logger.info(
+                    f"DRY RUN: Would have renamed {step.replay.path.name} => "
+                    f"{step.target.name}."
                 )
-                counts["renamed"] += 1
-            except Exception as e:
-                logger.error(f"Unexpected error handling {replay.path}: {e}")
-                counts["error"] += 1
-
-    if not dry_run:
-        with skipped_replays_file.open("at", encoding="utf-8") as f:
-            for path in skipped_paths:
-                print(path, file=f)
+        counts["renamed"] += len(plan)
+    else:
+        try:
+            finals = execute_plan(plan)
+        except Exception as e:
+            logger.error(
+                f"Renaming failed, so all renames in this batch were undone: {e}"
+            )
+            counts["error"] += 1
+            counts["rolled_back"] += len(plan)
+        else:
+            counts["renamed"] += len(plan)
+            for step, final in zip(plan, finals):
+                try:
+                    write_metadata(final, step.replay.summary, metadata)
+                except Exception as e:
+                    logger.error(f"Unexpected error writing metadata for {final}: {e}")
+                    counts["error"] += 1
 
     prefix = "DRY RUN: " if dry_run else ""
     counts_str = (
         "{renamed} replays renamed, "
-        "{skipped_new} skipped (no matching ladder game), "
-        "{skipped_ongoing} skipped (ongoing), "
         "{skipped_old} ignored (previously skipped), "
+        "{skipped_conflict} skipped (name taken), "
+        "{rolled_back} rolled back, "
         "{error} errors."
     ).format_map(counts)
     logger.info(prefix + counts_str)
+    return dict(counts)
 
 
 def backup_dir(replay_dir: Path, bu_dir: Path):
@@ -191,6 +313,37 @@ def naive_localtime_to_utc(dt: datetime) -> datetime:
     return dt.astimezone(timezone.utc).replace(tzinfo=None)
 
 
+def parse_timezone(setting: str) -> Optional[tzinfo]:
+    """Interpret a timezone setting for replay names: "utc", "local" (for which
+    None is returned, meaning the system's local time) or a fixed offset from
+    UTC like "+10:00" or "-0530"."""
+    if setting.lower() == "utc":
+        return timezone.utc
+    if setting.lower() == "local":
+        return None
+    if (m := TIMEZONE_OFFSET_PATTERN.fullmatch(setting.strip())) is None:
+        raise ValueError(
+            f"Unknown timezone {setting!r} (use utc, local or an offset like +10:00)"
+        )
+    sign, hours, minutes = m.groups()
+    offset = timedelta(hours=int(hours), minutes=int(minutes or 0))
+    return timezone(-offset if sign == "-" else offset)
+
+
+def utc_to_name_time(time: datetime, name_timezone: str = "utc") -> datetime:
+    """Convert a naive UTC time to the (timezone-aware) time to put in a
+    replay's name."""
+    return time.replace(tzinfo=timezone.utc).astimezone(parse_timezone(name_timezone))
+
+
+def name_time_to_utc(time: datetime, name_timezone: str = "utc") -> datetime:
+    """Convert a naive time read from a renamed replay's name back to UTC."""
+    tz = parse_timezone(name_timezone)
+    if tz is None:
+        return naive_localtime_to_utc(time)
+    return time.replace(tzinfo=tz).astimezone(timezone.utc).replace(tzinfo=None)
+
+
 class Replay(NamedTuple):
     path: Path
     summary: ReplaySummary
@@ -199,30 +352,32 @@ class Replay(NamedTuple):
     them: Optional[Player]
 
     @staticmethod
-    def from_path(path: Path):
+    def from_path(
+        path: PathLike, my_uuids: Sequence[UUID] = (), name_timezone: str = "utc"
+    ):
+        """Parse a replay, taking the time it was played from its filename.
+        Renamed replays are assumed to have been named using `name_timezone`
+        (see `parse_timezone`)."""
+        path = as_path(path)
         # Original names use local times:
         if m := re.search(r"(\d\d\d\d)\.(\d\d)\.(\d\d)-(\d\d).(\d\d)", path.name):
             time = naive_localtime_to_utc(
                 datetime(*(int(x) for x in m.groups()))  # type: ignore
             )
-        # Our renamed versions use UTC:
+        # Our renamed versions use UTC, unless configured otherwise:
         elif m := re.search(r"(\d\d\d\d)-(\d\d)-(\d\d) (\d\d).(\d\d)", path.name):
-            time = datetime(*(int(x) for x in m.groups()))  # type: ignore
+            time = name_time_to_utc(
+                datetime(*(int(x) for x in m.groups())), name_timezone  # type: ignore
+            )
         else:
             return None
 
         summary = summarize_replay(path)
+        # The account whose replay folder this is takes priority over any
+        # other accounts the user has told us about:
         our_uuid = find_our_uuid(path)
-
-        us = None
-        them = None
-        if len(summary.players) == 2:
-            if summary.players[0].uuid == our_uuid:
-                us, them = summary.players
-            elif summary.players[1].uuid == our_uuid:
-                them, us = summary.players
-        elif len(summary.players) > 0 and summary.players[0].uuid == our_uuid:
-            us = summary.players[0]
+        our_uuids = [our_uuid, *my_uuids] if our_uuid is not None else list(my_uuids)
+        us, them = summary.perspective(our_uuids)
 
         return Replay(path=path, time=time, us=us, them=them, summary=summary)
 
@@ -230,74 +385,105 @@ class Replay(NamedTuple):
 def find_our_uuid(replay_path: Path) -> Optional[UUID]:
     """Given the path to a Stormgate replay, extract the player UUID. (This
     assumes it's stored in the usual directory heirarchy.)"""
-    for part in reversed(replay_path.parts):
-        try:
-            return UUID(hex=part)
-        except ValueError:
-            pass
+    return uuid_from_path(replay_path)
 
 
 def get_result(replay: Replay):
     if not (replay.us and replay.them):
         return None
-    if replay.summary.build_number >= FRIGATE:
-        # Since Frigate we've had explicit surrender messages, so we rely on them alone for certainty:
-        if replay.us.leave_reason == "surrender":
-            return "loss"
-        if replay.them.leave_reason == "surrender":
-            return "win"
-        return None
-    else:
-        # For old replays, best we can do is guess based on disconnection times:
-        t1 = replay.us.disconnect_time
-        t2 = replay.them.disconnect_time
-        if t1 and t2:
-            return "win" if t1 > t2 else "loss"
-        elif t1:
-            return "loss"
-        elif t2:
-            return "win"
+    return infer_result(replay.us, replay.them, replay.summary.build_number)
+
 
+def get_result_confidence(replay: Replay) -> ResultConfidence:
+    if not (replay.us and replay.them):
+        return ResultConfidence.unknown
+    return judge_result(replay.us, replay.them, replay.summary.build_number)[1]
 
 
-def rename_replay(
+class PlannedRename(NamedTuple):
+    """One step of a batch of renames, worked out before anything is moved."""
+
+    replay: Replay
+    target: Path
+    """Where the replay will end up (which may be where it already is)"""
+    overwrite: bool = False
+    """Whether an existing file at `target` will be replaced"""
+    mtime: Optional[float] = None
+    """New modification time to give the replay, if not keeping its own"""
+
+
+def plan_rename(
     replay: Replay,
-    dry_run: bool,
     format_1v1: str,
     format_generic: str,
-):
+    language: str = "en",
+    display_names: Optional[Mapping[str, str]] = None,
+    on_conflict: ConflictStrategy = "skip",
+    claimed: Optional[Set[Path]] = None,
+    long_paths: bool = False,
+    mtime_mode: MtimeMode = "preserve",
+    transliterate_nicknames: bool = False,
+    name_timezone: str = "utc",
+) -> Optional[PlannedRename]:
+    """Work out where a replay should be renamed to, or None if it should be
+    left alone because the name is taken (see `resolve_target`)."""
     parts = {}
+
+    def name(key: Optional[str]) -> str:
+        return display_name(key, language, display_names)
+
+    def nick(nickname: str) -> str:
+        return normalize_nickname(nickname, transliterate=transliterate_nicknames)
+
+    def base_name(player: Player) -> str:
+        # AIs have no nickname, so they're named by type and faction instead:
+        if player.is_ai and player.display_name:
+            return player.display_name
+        return nick(player.nickname)
+
+    # Mirror names (e.g. both players called "Pox") get a discriminator or slot
+    # number added, so that you can still tell who's who from the filename:
+    players = replay.summary.players
+    names = disambiguate_nicknames(
+        [base_name(p) for p in players],
+        [p.nickname_discriminator for p in players],
+        [p.slot_number for p in players],
+    )
+
+    def player_name(player: Player) -> str:
+        matches = (n for p, n in zip(players, names) if p is player)
+        return next(matches, base_name(player))
+
     parts["map_name"] = replay.summary.map_name
     parts["build_number"] = replay.summary.build_number
-    duration = replay.summary.duration_seconds
-    if duration is not None:
-        minutes, seconds = divmod(int(duration), 60)
-        parts["duration"] = f"{minutes:02d}m{seconds:02d}s"
-    else:
-        parts["duration"] = ""
+    parts["duration"] = format_duration(replay.summary.duration_seconds)
+    mod = replay.summary.mod_info
+    parts["mod"] = f"[Mod {mod.workshop_id}]" if mod is not None else ""
 
-    parts["time"] = replay.time
+    parts["time"] = utc_to_name_time(replay.time, name_timezone)
 
     us = replay.us
     them = replay.them
     if us and them:
         # 1v1
-        parts["us"] = parts["p1"] = us.nickname
-        parts["them"] = parts["p2"] = them.nickname
+        parts["us"] = parts["p1"] = player_name(us)
+        parts["them"] = parts["p2"] = player_name(them)
 
-        parts["r1"] = parts["f1"] = (us.faction or "").capitalize()
-        parts["r2"] = parts["f2"] = (them.faction or "").capitalize()
+        parts["r1"] = parts["f1"] = name(us.faction)
+        parts["r2"] = parts["f2"] = name(them.faction)
 
         result = get_result(replay)
-        parts["result"] = (result or "unknown").capitalize()
+        parts["result"] = name(result)
 
         newname = format_1v1.format(**parts)
     else:
         parts["players"] = ", ".join(
-            p.nickname.capitalize() for p in replay.summary.players
+            n if p.is_ai else n.capitalize() for p, n in zip(players, names)
         )
         parts["players_with_factions"] = ", ".join(
-            f"{p.nickname.capitalize()} {(p.faction or '').upper():.1}" for p in replay.summary.players
+            f"{n if p.is_ai else n.capitalize()} "
+            f"{(p.faction or '').upper():.1}"
+            for p, n in zip(players, names)
         )
         newname = format_generic.format(**parts)
 
@@ -305,69 +491,365 @@ def rename_replay(
     newname = re.sub(r"\s+", " ", newname)
 
     target = replay.path.parent / newname
-    do_rename(replay.path, target, dry_run=dry_run)
-
-
-def do_rename(source: Path, target: Path, dry_run: bool):
+    if platform.system() == "Windows":
+        target = target.with_name(sanitize_filename(target.name))
+        if len(str(target)) >= MAX_PATH:
+            if long_paths:
+                target = windows_long_path(target)
+            else:
+                target = fit_path_length(target)
+    resolved = resolve_target(replay.path, target, on_conflict, claimed)
+    if resolved is None:
+        return None
+    target, overwrite = resolved
+    mtime = None
+    if mtime_mode == "game_time":
+        mtime = replay.time.replace(tzinfo=timezone.utc).timestamp()
+    return PlannedRename(replay, target, overwrite, mtime)
+
+
+def resolve_target(
+    source: Path,
+    target: Path,
+    on_conflict: ConflictStrategy = "skip",
+    claimed: Optional[Set[Path]] = None,
+) -> Optional[Tuple[Path, bool]]:
+    """Decide where source should be renamed to, returning the path and whether
+    an existing file there will be overwritten (or None to skip renaming it).
+    The chosen path is added to `claimed`.
+
+    `on_conflict` determines what happens if the target already exists (or is
+    in `claimed`, the set of targets already used in this batch):
+
+    * skip: leave the source alone
+    * counter: append " (2)", " (3)" etc. to the name until it's free
+    * overwrite: replace the existing file (only for files that existed before
+      this batch - batch collisions fall back to counter so we never clobber a
+      replay we just renamed)"""
+    if claimed is None:
+        claimed = set()
     if source == target:
         logger.debug(f"{source} already has the desired format, doing nothing :)")
-        return
+        claimed.add(target)
+        return target, False
 
-    if target.exists():
-        logger.error(f"Not renaming {source}! {target} already exists!")
-        return
+    overwrite = False
+    if target in claimed or target.exists():
+        if on_conflict == "skip":
+            logger.error(f"Not renaming {source}! {target} already exists!")
+            return None
+        elif on_conflict == "overwrite" and target not in claimed:
+            logger.warning(f"Overwriting existing {target} with {source.name}.")
+            overwrite = True
+        else:
+            target = next_free_name(target, claimed)
+            logger.warning(f"Target already exists, using {target.name} instead.")
+    claimed.add(target)
+    return target, overwrite
+
+
+def validate_plan(plan: Sequence[PlannedRename]) -> List[str]:
+    """Check a batch of renames can be carried out as planned, returning a
+    description of each problem found (so an empty list means all is well)."""
+    problems = []
+    sources = {step.replay.path for step in plan}
+    targets: Set[Path] = set()
+    for step in plan:
+        source, target = step.replay.path, step.target
+        if not source.exists():
+            problems.append(f"{source} no longer exists.")
+        if target in targets:
+            problems.append(f"More than one replay would be renamed to {target}.")
+        targets.add(target)
+        if target == source:
+            continue
+        if not target.parent.is_dir():
+            problems.append(f"Can't rename {source.name}: no folder {target.parent}.")
+        elif target in sources:
+            problems.append(f"Can't rename {source.name}: {target} is being renamed.")
+        elif target.exists() and not step.overwrite:
+            problems.append(f"Can't rename {source.name}: {target} already exists.")
+    return problems
+
+
+def execute_plan(plan: Sequence[PlannedRename]) -> List[Path]:
+    """Carry out a (validated) batch of renames, returning the path each
+    replay ended up at.
+
+    This is all-or-nothing: if any rename fails, those already done are undone
+    (and any replaced files put back) before the error is re-raised, so a
+    failure partway through never leaves the folder half-renamed."""
+    done: List[Tuple[Path, Path, os.stat_result]] = []
+    replaced: List[Tuple[Path, Path]] = []
+    finals = []
+    try:
+        for step in plan:
+            source, target = step.replay.path, step.target
+            if source == target:
+                finals.append(source)
+                continue
+            if step.overwrite and target.exists():
+                # Keep the file we're replacing until the whole batch is done:
+                aside = target.with_name(f".{target.name}.replaced")
+                move_file(target, aside, overwrite=True)
+                replaced.append((aside, target))
+            stat = source.stat()
+            logger.info(f"Renaming {source.name} => {target.name}.")
+            try:
+                move_replay_file(source, target, mtime=step.mtime)
+            except OSError as e:
+                if target.exists() and not source.exists():
+                    # The replay itself was moved before something went wrong
+                    done.append((source, target, stat))
+                    raise
+                # In case the error was due to weird characters in a player name:
+                sanitized = target.with_name(sanitize_filename(target.name))
+                if sanitized == target or sanitized.exists():
+                    raise
+                logger.warning(
+                    f"Error renaming {source} => {target.name} ({e}), "
+                    "retrying with sanitized filename."
+                )
+                target = sanitized
+                move_replay_file(source, target, mtime=step.mtime)
+            done.append((source, target, stat))
+            finals.append(target)
+    except BaseException:
+        rollback(done, replaced)
+        raise
+    for aside, _ in replaced:
+        aside.unlink(missing_ok=True)
+    return finals
+
+
+def rollback(
+    done: Sequence[Tuple[Path, Path, os.stat_result]],
+    replaced: Sequence[Tuple[Path, Path]] = (),
+):
+    """Undo renames performed by `execute_plan`, most recent first, restoring
+    the original modification times and any files that were overwritten.
+    Renames that can't be undone are left in the journal for `undo_last`."""
+    undone = set()
+    for source, target, stat in reversed(done):
+        try:
+            logger.info(f"Rolling back {target.name} => {source.name}.")
+            move_file(target, source)
+            move_sidecars(target, source)
+            os.utime(source, ns=(stat.st_atime_ns, stat.st_mtime_ns))
+            undone.add((source, target))
+        except Exception as e:
+            logger.error(f"Couldn't roll back rename of {source} => {target}: {e}")
+    for aside, original in reversed(replaced):
+        try:
+            move_file(aside, original, overwrite=True)
+        except Exception as e:
+            logger.error(f"Couldn't restore {original} (kept as {aside}): {e}")
+    forget_renames(undone)
+
+
+def next_free_name(target: Path, claimed: Set[Path]) -> Path:
+    """Find a variant of target (foo (2).SGReplay etc.) that isn't taken."""
+    i = 2
+    while True:
+        candidate = target.with_name(f"{target.stem} ({i}){target.suffix}")
+        if candidate not in claimed and not candidate.exists():
+            return candidate
+        i += 1
 
-    if dry_run:
-        logger.info(f"DRY RUN: Would have renamed {source.name} => {target.name}.")
-        return
 
-    logger.info(f"Renaming {source.name} => {target.name}.")
+def move_replay_file(
+    source: Path, target: Path, overwrite: bool = False, mtime: Optional[float] = None
+):
+    """Move a replay along with its sidecar files (if any), recording the
+    move in the rename journal so it can be undone.
+
+    The game lists replays by modification time, so we make sure the moved
+    file keeps its original one (or gets `mtime`, if given)."""
+    stat = source.stat()
+    move_file(source, target, overwrite=overwrite)
+    if mtime is None:
+        os.utime(target, ns=(stat.st_atime_ns, stat.st_mtime_ns))
+    else:
+        os.utime(target, (stat.st_atime, mtime))
+    move_sidecars(source, target)
+    with journal_file.open("at", encoding="utf-8") as f:
+        entry = JournalEntry(old=source, new=target, renamed_at=datetime.now())
+        print(entry.model_dump_json(), file=f)
+
+
+def move_file(source: Path, target: Path, overwrite: bool = False):
+    """Atomically move a file where possible. Between filesystems (e.g. onto a
+    network drive), where that isn't possible, the file is copied to a
+    temporary name beside the target and synced to disk before being moved
+    into place and the source deleted - so an interrupted move never leaves a
+    half-written file under the target name."""
     try:
-        source.rename(target)
-    except Exception as e:
-        # In case the error was due to weird characters in a player name:
-        new_name = sanitize_filename(target.name)
-        if new_name != target.name:
-            logger.warning(
-                f"Error renaming {source} => {target.name}, retrying with sanitized filename."
-            )
-            do_rename(source, target.parent / new_name, dry_run=dry_run)
+        if overwrite:
+            source.replace(target)
         else:
-            logger.error(f"Error renaming {source} => {target.name}: {e}")
+            source.rename(target)
+        return
+    except OSError as e:
+        if e.errno != errno.EXDEV:
+            raise
+    if target.exists() and not overwrite:
+        raise FileExistsError(errno.EEXIST, "Target already exists", str(target))
+    logger.debug(f"{source} and {target} are on different filesystems, copying.")
+    partial = target.with_name(f".{target.name}.partial")
+    try:
+        with source.open("rb") as src, partial.open("wb") as dst:
+            copyfileobj(src, dst)
+            dst.flush()
+            os.fsync(dst.fileno())
+        copystat(source, partial)
+        partial.replace(target)
+    except BaseException:
+        partial.unlink(missing_ok=True)
+        raise
+    source.unlink()
+
+
+def move_sidecars(source: Path, target: Path):
+    """Move the metadata and chunk index sidecars (if any) that belong to a
+    replay along with it."""
+    for suffix in (SIDECAR_SUFFIX, INDEX_SUFFIX):
+        old_sidecar = source.parent / f"{source.name}{suffix}"
+        if old_sidecar.exists():
+            move_file(old_sidecar, target.parent / f"{target.name}{suffix}")
+
+
+class JournalEntry(BaseModel):
+    """A record of a replay being renamed or moved."""
+
+    old: Path
+    new: Path
+    renamed_at: datetime
+
+
+def read_journal() -> List[JournalEntry]:
+    if not journal_file.exists():
+        return []
+    return [
+        JournalEntry.model_validate_json(line)
+        for line in journal_file.read_text(encoding="utf-8").splitlines()
+        if line
+    ]
 
 
-def sanitize_filename(filename: str) -> str:
-    """Remove bad characters from a filename"""
-    return BAD_CHARS.sub("", filename)
+def forget_renames(moves: Set[Tuple[Path, Path]]):
+    """Remove the given (old, new) renames from the journal."""
+    if not moves:
+        return
+    entries = read_journal()
+    with journal_file.open("wt", encoding="utf-8") as f:
+        for entry in entries:
+            if (entry.old, entry.new) not in moves:
+                print(entry.model_dump_json(), file=f)
+
+
+def undo_last(n: int) -> int:
+    """Undo the last `n` renames recorded in the journal, most recent first.
+    Returns the number of renames successfully undone."""
+    entries = read_journal()
+    split = max(len(entries) - n, 0)
+    keep, undo = entries[:split], entries[split:]
+    undone = 0
+    failed = []
+    for entry in reversed(undo):
+        if not entry.new.exists():
+            logger.error(f"Can't undo rename of {entry.old}: {entry.new} no longer exists.")
+            failed.append(entry)
+            continue
+        if entry.old.exists():
+            logger.error(f"Can't undo rename of {entry.new}: {entry.old} already exists.")
+            failed.append(entry)
+            continue
+        logger.info(f"Restoring {entry.new.name} => {entry.old.name}.")
+        entry.old.parent.mkdir(parents=True, exist_ok=True)
+        move_file(entry.new, entry.old)
+        move_sidecars(entry.new, entry.old)
+        undone += 1
+    # Entries we failed to undo stay in the journal in case the user fixes the
+    # problem and tries again:
+    with journal_file.open("wt", encoding="utf-8") as f:
+        for entry in [*keep, *reversed(failed)]:
+            print(entry.model_dump_json(), file=f)
+    return undone
+
+
+def undo_all() -> int:
+    """Undo every rename recorded in the journal."""
+    return undo_last(len(read_journal()))
+
+
+def write_metadata(path: Path, summary: ReplaySummary, mode: MetadataMode):
+    """Store the parsed summary of a replay alongside it, so other tools (and
+    future runs) can read the match info without re-parsing.
+
+    In "json" mode this is a sidecar file named e.g. foo.SGReplay.json; in
+    "xattr" mode it's an extended attribute on the replay itself (only
+    supported on Linux)."""
+    if mode == "none":
+        return
+    content = summary.model_dump_json()
+    if mode == "json":
+        sidecar = path.parent / f"{path.name}{SIDECAR_SUFFIX}"
+        sidecar.write_text(content, encoding="utf-8")
+    elif mode == "xattr":
+        try:
+            os.setxattr(path, XATTR_NAME, content.encode("utf-8"))  # type: ignore
+        except (AttributeError, OSError) as e:
+            logger.warning(f"Could not write metadata attribute on {path.name}: {e}")
 
 
-def guess_replay_dir() -> Optional[Path]:
-    if platform.system() == "Windows":
-        # Should be easy, just look in the current user's local app data
-        appdata = os.environ["LOCALAPPDATA"]
-        paths = [Path(appdata) / "Stormgate" / "Saved" / "Replays"]
-    else:
-        # If this script is running on Linux and Stormgate is installed using
-        # Steam+Proton, look in the steam compatdata:
-        steammnt = (
-            Path.home()
-            / ".steam/root/steamapps/compatdata/2012510/pfx/dosdevices"
-        )
+def read_metadata(path: Path) -> Optional[ReplaySummary]:
+    """Read back a summary stored by `write_metadata`, if there is one."""
+    sidecar = path.parent / f"{path.name}{SIDECAR_SUFFIX}"
+    if sidecar.exists():
+        return ReplaySummary.model_validate_json(sidecar.read_text(encoding="utf-8"))
+    try:
+        content = os.getxattr(path, XATTR_NAME)  # type: ignore
+    except (AttributeError, OSError):
+        return None
+    return ReplaySummary.model_validate_json(content)
 
-        # If this script is running on the Windows Subsystem for Linux, we can
-        # find the Windows drives mounted in /mnt:
-        wslmnt = Path("/mnt")
 
-        tail = "AppData/Local/Stormgate/Saved/Replays"
-        paths = [
-            *steammnt.glob(f"*/users/steamuser/{tail}"),
-            *wslmnt.glob(f"*/Users/*/{tail}"),
-            *wslmnt.glob(f"*/Documents and Settings/*/{tail}"),
-        ]
+def sanitize_filename(filename: str) -> str:
+    """Remove bad characters from a filename, and make sure Windows will accept
+    it (no trailing dots/spaces, no reserved device names like CON)."""
+    filename = BAD_CHARS.sub("", filename).rstrip(". ")
+    if filename.split(".")[0].strip().upper() in WINDOWS_RESERVED_NAMES:
+        filename = f"_{filename}"
+    return filename
+
+
+def fit_path_length(target: Path, max_length: int = MAX_PATH) -> Path:
+    """Truncate the stem of a filename so the full path fits within Windows'
+    MAX_PATH limit (which includes a terminating NUL)."""
+    excess = len(str(target)) - (max_length - 1)
+    if excess <= 0:
+        return target
+    stem = target.stem[:-excess].rstrip(". ")
+    if not stem:
+        raise ValueError(f"Directory path too long to fit any filename: {target.parent}")
+    logger.warning(f"Shortening {target.name} to fit within Windows path limits.")
+    return target.with_name(f"{stem}{target.suffix}")
+
+
+def windows_long_path(path: Path) -> Path:
+    r"""Add the \\?\ prefix that lets Windows APIs accept paths beyond MAX_PATH."""
+    text = str(path.absolute())
+    if text.startswith("\\\\?\\"):
+        return path
+    if text.startswith("\\\\"):
+        # UNC path: \\server\share -> \\?\UNC\server\share
+        return Path("\\\\?\\UNC\\" + text[2:])
+    return Path("\\\\?\\" + text)
 
-    for path in paths:
-        if path.is_dir():
-            return path
+
+def guess_replay_dir() -> Optional[Path]:
+    dirs = find_replay_dirs()
+    return dirs[0] if dirs else None
 
 
 def validate_format_string(format: str, type: Union[Literal["1v1"], Literal["generic"]]):
@@ -1,17 +1,41 @@
 """Stormgate replay parsing tools"""
 from __future__ import annotations
+from bisect import bisect_left
+import codecs
 from collections import defaultdict
-from contextlib import contextmanager
-from enum import IntEnum
+from contextlib import ExitStack, contextmanager
+from functools import lru_cache
+from itertools import islice
+from enum import Enum, IntEnum
 import gzip
+import io
+import os
+import re
 from pathlib import Path
 import struct
-from typing import BinaryIO, Dict, Iterable, List, Optional, Union
+import time
+from typing import (
+    Any,
+    BinaryIO,
+    Callable,
+    Dict,
+    Iterable,
+    List,
+    NamedTuple,
+    Optional,
+    Sequence,
+    Tuple,
+    Union,
+)
 from uuid import UUID
+import zlib
 
-from pydantic import BaseModel
+from google.protobuf.message import DecodeError
+from pydantic import BaseModel, Field, PrivateAttr
+from typing_extensions import Literal, Protocol
 
 from . import stormgate_pb2 as pb
+from .names import disambiguate_nicknames
 
 import logging
 
@@ -22,33 +46,339 @@ logger = logging.getLogger(__name__)
 # This is very close to 1/1024 of a second, so we'll assume that this is the exact value.
 REPLAY_TIMESTAMP_UNIT = 1 / 1024
 
+
+def ticks_per_second(build_number: Optional[int] = None) -> float:
+    """Number of replay timestamp units per second of game time.
+
+    This has been 1024 in every build we've looked at; it takes the build
+    number so that if it ever changes, there's one place to fix it."""
+    return 1 / REPLAY_TIMESTAMP_UNIT
+
 FRIGATE = 55366
 
+ACTIVITY_BUCKET_SECONDS = 10
+"""Width of the time intervals in which client activity is counted"""
+
+BURST_GAP_SECONDS = 1.0
+"""Commands sent less than this far apart count as one burst (see
+`Client.bursts`)"""
+
+EARLY_LEAVE_SECONDS = 60.0
+"""Default grace period: leaving before this much game time has passed counts
+as leaving early (ladder stats commonly discard such games)"""
+
+GZIP_MAGIC = b"\x1f\x8b\x08"
+"""Magic bytes (plus deflate method byte) at the start of a gzip stream"""
+
+HEADER_SCAN_LENGTH = 64
+"""How far into the file to look for the start of the gzip stream"""
+
+FILE_SIGNATURES: List[Tuple[bytes, str]] = [
+    (GZIP_MAGIC[:2], "a plain gzip file"),
+    (b"PK\x03\x04", "a zip archive"),
+    (b"7z\xbc\xaf\x27\x1c", "a 7-Zip archive"),
+    (b"Rar!", "a RAR archive"),
+    (b"BZh", "a bzip2 file"),
+    (b"\xfd7zXZ\x00", "an xz file"),
+    (b"\x89PNG", "a PNG image"),
+    (b"\xff\xd8\xff", "a JPEG image"),
+    (b"GIF8", "a GIF image"),
+    (b"%PDF", "a PDF document"),
+    (b"MZ", "a Windows executable"),
+    (b"\x7fELF", "a Linux executable"),
+]
+"""Magic bytes of file types that get mistaken for replays, for error messages"""
+
+
+class HeaderLayout(NamedTuple):
+    """Byte offsets of the fields in the uncompressed replay header.
+
+    Every replay we've seen so far has a 16-byte header ending with the build
+    number, but we detect this rather than assuming it in case the game ever
+    changes the header length."""
+
+    build_number_offset: int
+    gzip_offset: int
+
+
+PathLike = Union[str, bytes, "os.PathLike[str]", "os.PathLike[bytes]"]
+"""Anything usable as a filesystem path, including bytes paths that aren't
+valid UTF-8 (which some localized Windows user folders produce)"""
+
+class Readable(Protocol):
+    """Anything replay data can be read from: not just files, but also
+    sockets (via `makefile`), zip archive members and so on."""
+
+    def read(self, size: int = -1) -> bytes:
+        ...
+
+
+ReplaySource = Union[PathLike, BinaryIO, Readable]
+"""A replay file path or a binary stream (which we never close - that's up to
+whoever opened it). Replay data that's already in memory can be passed by
+wrapping it in `io.BytesIO`; bare bytes are taken to be a path."""
+
+
+def as_path(path: PathLike) -> Path:
+    return Path(os.fsdecode(path))
+
+
+def seekable(replay: ReplaySource) -> Union[Path, BinaryIO]:
+    """Turn any path into a `Path`, and buffer a replay stream in memory if it
+    can't seek (e.g. a pipe), since we need to jump back and forth within the
+    header."""
+    if isinstance(replay, (str, bytes, os.PathLike)):
+        return as_path(replay)
+    is_seekable = getattr(replay, "seekable", None)
+    if is_seekable is not None and is_seekable():
+        return replay  # type: ignore
+    return io.BytesIO(replay.read())
+
+
+def sniff_file_type(head: bytes) -> Optional[str]:
+    """Guess what a file that isn't a replay actually is, from its first few
+    bytes (see `FILE_SIGNATURES`)."""
+    if not head:
+        return "an empty file"
+    for magic, description in FILE_SIGNATURES:
+        if head.startswith(magic):
+            return description
+    try:
+        # (Incrementally, so a character cut in half at the end is okay)
+        text = codecs.getincrementaldecoder("utf-8")().decode(head)
+    except UnicodeDecodeError:
+        return None
+    if all(c.isprintable() or c.isspace() for c in text):
+        return "a text file"
+    return None
+
+
+def detect_header_layout(
+    replay: ReplaySource, gzip_offset: Optional[int] = None
+) -> HeaderLayout:
+    """Find where the gzip stream starts in a replay file.
+
+    The build number is taken to be the 4 bytes immediately preceding the gzip
+    stream. Pass `gzip_offset` to skip detection for weird files."""
+    replay = seekable(replay)
+    if gzip_offset is None:
+        if isinstance(replay, Path):
+            with replay.open("rb") as f:
+                head = f.read(HEADER_SCAN_LENGTH)
+        else:
+            replay.seek(0)
+            head = replay.read(HEADER_SCAN_LENGTH)
+            replay.seek(0)
+        gzip_offset = head.find(GZIP_MAGIC)
+        if gzip_offset < 4:
+            problem = (
+                f"Could not find gzip stream in first {HEADER_SCAN_LENGTH} bytes"
+                if gzip_offset < 0
+                else "Missing the header that precedes the gzip stream"
+            )
+            raise NotAReplayError(problem, sniff_file_type(head))
+        (build_number,) = struct.unpack_from("<i", head, gzip_offset - 4)
+        if build_number <= 0:
+            raise NotAReplayError(f"Implausible build number {build_number} in header")
+        if gzip_offset != 16:
+            logger.debug(f"Detected non-standard replay header length {gzip_offset}")
+    return HeaderLayout(build_number_offset=gzip_offset - 4, gzip_offset=gzip_offset)
+
+
 @contextmanager
-def decompress(replay: Union[Path, BinaryIO]):
-    """Open a gzipped stormgate replay, skipping the 16-byte header."""
-    if isinstance(replay, Path):
-        replay = replay.open("rb")
-    with replay:
-        replay.seek(16)
-        with gzip.GzipFile(fileobj=replay) as f2:
-            yield f2
+def decompress(
+    replay: ReplaySource,
+    gzip_offset: Optional[int] = None,
+    recover: bool = False,
+    max_bytes: Optional[int] = None,
+):
+    """Open a gzipped stormgate replay, skipping the (usually 16-byte) header.
+
+    If `recover` is set, the compressed data is decoded up front in a way that
+    tolerates truncated/corrupt streams (e.g. from game crashes), salvaging
+    everything decodable instead of raising part-way through. (`max_bytes` caps
+    the size of that up-front decoding - see `salvage_gzip`.)"""
+    replay = seekable(replay)
+    layout = detect_header_layout(replay, gzip_offset)
+    with ExitStack() as stack:
+        if isinstance(replay, Path):
+            replay = stack.enter_context(replay.open("rb"))
+        replay.seek(layout.gzip_offset)
+        if recover:
+            yield io.BytesIO(salvage_gzip(replay.read(), max_bytes))
+        else:
+            with gzip.GzipFile(fileobj=replay) as f2:
+                yield f2
+
+
+def salvage_gzip(data: bytes, max_bytes: Optional[int] = None) -> bytes:
+    """Decompress as much as possible of a (possibly multi-member) gzip stream,
+    stopping quietly at the first truncated or corrupt member.
+
+    Raises `SimulationLimitExceeded` if the output would be over `max_bytes`."""
+    out: List[bytes] = []
+    size = 0
+    while data:
+        decoder = zlib.decompressobj(16 + zlib.MAX_WBITS)
+        # (A max_length of 0 means no limit)
+        room = 0 if max_bytes is None else max_bytes - size + 1
+        try:
+            out.append(decoder.decompress(data, room))
+        except zlib.error as e:
+            logger.warning(f"Corrupt gzip data, salvaging what we can: {e}")
+            # The decompressobj has already given up, so redo this member in
+            # small steps to get as much output as possible before the error.
+            out.append(_salvage_member(data, room))
+            data = b""
+        size += len(out[-1])
+        if max_bytes is not None and size > max_bytes:
+            raise SimulationLimitExceeded("max_bytes", max_bytes)
+        if not data:
+            break
+        if not decoder.eof:
+            logger.warning("Truncated gzip data, salvaging what we can")
+            out.append(decoder.flush())
+            break
+        data = decoder.unused_data
+    return b"".join(out)
 
 
-def get_build_number(replay: Union[Path, BinaryIO]) -> int:
+def _salvage_member(data: bytes, max_length: int = 0, step: int = 1024) -> bytes:
+    decoder = zlib.decompressobj(16 + zlib.MAX_WBITS)
+    out = []
+    size = 0
+    for i in range(0, len(data), step):
+        try:
+            out.append(decoder.decompress(data[i : i + step], max_length))
+        except zlib.error:
+            break
+        size += len(out[-1])
+        if max_length and size >= max_length:
+            break
+    return b"".join(out)
+
+
+def get_build_number(
+    replay: ReplaySource, gzip_offset: Optional[int] = None
+) -> int:
     """Find the Stormgate version number that produced a given replay file.
 
     This is the number that can be found at the start of unrenamed replays,
     e.g. 44420 in CL44420-2024.01.31-16.23.SGReplay; but it is also stored in
-    the 16-byte header, so we get it from there instead."""
+    the header just before the compressed data, so we get it from there instead."""
+    replay = seekable(replay)
+    layout = detect_header_layout(replay, gzip_offset)
     if isinstance(replay, Path):
-        replay = replay.open("rb")
-    replay.seek(12)
+        with replay.open("rb") as f:
+            f.seek(layout.build_number_offset)
+            (x,) = struct.unpack("<i", f.read(4))
+        return x
+    replay.seek(layout.build_number_offset)
     (x,) = struct.unpack("<i", replay.read(4))
     replay.seek(0)
     return x
 
 
+class ReplayHeader(BaseModel):
+    """The uncompressed header at the start of a replay file.
+
+    We only really understand the build number; the other fields are named for
+    their apparent role and exposed so they can be investigated."""
+
+    magic: bytes
+    """First 4 bytes of the file"""
+    flags: int
+    """Next 4 bytes, as a little-endian unsigned int"""
+    reserved: bytes
+    """Remaining bytes before the build number"""
+    build_number: int
+    header_length: int
+    """Offset at which the gzipped data starts"""
+
+
+def read_header(
+    replay: ReplaySource, gzip_offset: Optional[int] = None
+) -> ReplayHeader:
+    """Parse a replay's header without decompressing anything."""
+    replay = seekable(replay)
+    layout = detect_header_layout(replay, gzip_offset)
+    if isinstance(replay, Path):
+        with replay.open("rb") as f:
+            head = f.read(layout.gzip_offset)
+    else:
+        replay.seek(0)
+        head = replay.read(layout.gzip_offset)
+        replay.seek(0)
+    (flags,) = struct.unpack("<I", head[4:8])
+    (build_number,) = struct.unpack(
+        "<i", head[layout.build_number_offset : layout.build_number_offset + 4]
+    )
+    return ReplayHeader(
+        magic=head[:4],
+        flags=flags,
+        reserved=head[8 : layout.build_number_offset],
+        build_number=build_number,
+        header_length=layout.gzip_offset,
+    )
+
+
+def replay_size(replay: Union[Path, BinaryIO]) -> int:
+    """Size in bytes of a replay file or (seekable) stream."""
+    if isinstance(replay, Path):
+        return replay.stat().st_size
+    position = replay.tell()
+    size = replay.seek(0, io.SEEK_END)
+    replay.seek(position)
+    return size
+
+
+MAP_SCAN_CHUNKS = 16
+"""How many chunks at the start of a replay to search for the map details
+(normally the very first message)"""
+
+
+def decompress_replay(
+    replay: ReplaySource, out_path: PathLike, gzip_offset: Optional[int] = None
+) -> int:
+    """Write the decompressed contents of a replay to `out_path`, returning the
+    number of bytes written.
+
+    This is equivalent to `tail -c +17 replay.SGReplay | zcat > out_path`, but
+    doesn't need a unix shell."""
+    written = 0
+    with decompress(replay, gzip_offset) as f, as_path(out_path).open("wb") as out:
+        while block := f.read(1 << 16):
+            written += out.write(block)
+    return written
+
+
+def repack_replay(
+    raw_path: PathLike,
+    out_path: PathLike,
+    build_number: int,
+    template: Optional[PathLike] = None,
+):
+    """Turn a decompressed message stream (as produced by `decompress_replay`)
+    back into a .SGReplay file by gzipping it and prepending a header.
+
+    We don't yet know what the first 12 bytes of the header mean, so if a
+    `template` replay is given they're copied from there; otherwise they're
+    left as zeros."""
+    raw_path, out_path = as_path(raw_path), as_path(out_path)
+    template = as_path(template) if template is not None else None
+    prefix = bytes(12)
+    if template is not None:
+        layout = detect_header_layout(template)
+        with template.open("rb") as f:
+            prefix = f.read(layout.build_number_offset)
+    with raw_path.open("rb") as raw, out_path.open("wb") as out:
+        out.write(prefix)
+        out.write(struct.pack("<i", build_number))
+        with gzip.GzipFile(fileobj=out, mode="wb") as gz:
+            while block := raw.read(1 << 16):
+                gz.write(block)
+
+
 def read_varint(f) -> Optional[int]:
     """Read a base-7 varint from a binary stream, or return None if the stream is at EOF."""
     bs = f.read(1)
@@ -64,17 +394,386 @@ def read_varint(f) -> Optional[int]:
     return digits
 
 
-def split_replay(replay: Union[Path, BinaryIO]) -> Iterable[bytes]:
+def write_varint(f, value: int):
+    """Write a base-7 varint to a binary stream."""
+    while True:
+        byte = value & 0b01111111
+        value >>= 7
+        if value:
+            f.write(bytes([byte | 0b10000000]))
+        else:
+            f.write(bytes([byte]))
+            return
+
+
+def split_replay(
+    replay: ReplaySource,
+    gzip_offset: Optional[int] = None,
+    recover: bool = False,
+) -> Iterable[bytes]:
     """Split a replay into a sequence of chunks, each of which is a raw
-    bytestring containing a wire-format encoding of a protobuf message."""
-    with decompress(replay) as f:
+    bytestring containing a wire-format encoding of a protobuf message.
+
+    With `recover` set, corrupt compressed data and a truncated final chunk
+    end the sequence early (with a warning) instead of raising."""
+    for _, bytestring in split_replay_with_offsets(replay, gzip_offset, recover):
+        yield bytestring
+
+
+MAX_CHUNK_LENGTH = 1 << 20
+"""Longest chunk we'll believe in. Real ones are at most a few KiB, so a longer
+length prefix means the stream is corrupt (or crafted to make us buffer it)."""
+
+
+def split_replay_with_offsets(
+    replay: ReplaySource,
+    gzip_offset: Optional[int] = None,
+    recover: bool = False,
+    max_bytes: Optional[int] = None,
+    max_seconds: Optional[float] = None,
+) -> Iterable[Tuple[int, bytes]]:
+    """As `split_replay`, but yielding (offset, bytestring) pairs, where offset
+    is the position of the chunk within the decompressed stream.
+
+    Going over `max_bytes` of decompressed data, or `max_seconds` of wall clock
+    time (including time spent by the caller between chunks), raises
+    `SimulationLimitExceeded`."""
+    started = time.perf_counter()
+    with decompress(replay, gzip_offset, recover=recover, max_bytes=max_bytes) as f:
         while True:
-            length = read_varint(f)
+            if max_seconds is not None and time.perf_counter() - started > max_seconds:
+                raise SimulationLimitExceeded("max_seconds", max_seconds)
+            try:
+                length = read_varint(f)
+            except ValueError:
+                if not recover:
+                    raise
+                logger.warning("Replay ends part-way through a chunk length")
+                break
             if length is None:
                 break
+            offset = f.tell()
+            if length > MAX_CHUNK_LENGTH:
+                message = f"Chunk at offset {offset} claims to be {length} bytes long"
+                if not recover:
+                    raise ReplayParsingError(message)
+                logger.warning(f"{message}, ignoring the rest of the replay")
+                break
+            if max_bytes is not None and offset + length > max_bytes:
+                raise SimulationLimitExceeded("max_bytes", max_bytes)
+            bytestring = f.read(length)
+            if recover and len(bytestring) < length:
+                logger.warning("Replay ends part-way through a chunk, discarding it")
+                break
+            yield offset, bytestring
+
+
+HEXDUMP_BYTES = 64
+"""How much of an undecodable chunk to include in error messages"""
+
+
+def hexdump(data: bytes, limit: Optional[int] = HEXDUMP_BYTES) -> str:
+    """Format bytes in the classic 16-per-line hex + ASCII layout."""
+    truncated = limit is not None and len(data) > limit
+    data = data[:limit]
+    lines = []
+    for start in range(0, len(data), 16):
+        row = data[start : start + 16]
+        text = "".join(chr(b) if 32 <= b < 127 else "." for b in row)
+        lines.append(f"{start:08x}  {row.hex(' '):<47}  |{text}|")
+    if truncated:
+        lines.append("...")
+    return "\n".join(lines)
+
+
+def parse_chunk(
+    bytestring: bytes, offset: int, chunk: Optional[pb.ReplayChunk] = None
+) -> pb.ReplayChunk:
+    """Decode a chunk (into `chunk`, if given), raising a `ChunkDecodeError`
+    describing where it came from if it's malformed."""
+    if chunk is None:
+        chunk = pb.ReplayChunk()
+    try:
+        chunk.ParseFromString(bytestring)
+    except DecodeError as e:
+        raise ChunkDecodeError(offset, bytestring, str(e)) from e
+    return chunk
+
+
+class ChunkFrame(NamedTuple):
+    """Location of a single chunk within the decompressed replay stream."""
+
+    offset: int
+    """Offset of the chunk's message bytes (after its length prefix)"""
+    length: int
+
+
+def scan_chunk_frames(data: bytes) -> List[ChunkFrame]:
+    """Walk the length-prefix framing of a decompressed replay stream without
+    decoding any protobuf, returning where each chunk lives.
+
+    This is cheap compared to decoding, so it's useful for getting an overview
+    of a replay (or slicing it up) before committing to a full parse."""
+    frames = []
+    pos = 0
+    end = len(data)
+    while pos < end:
+        length = 0
+        shift = 0
+        while True:
+            if pos >= end:
+                raise ValueError("EOF encountered while parsing varint")
+            byte = data[pos]
+            pos += 1
+            length |= (byte & 0b01111111) << shift
+            shift += 7
+            if not byte & 0b10000000:
+                break
+        frames.append(ChunkFrame(offset=pos, length=length))
+        pos += length
+    return frames
+
+
+class UnknownChunk(NamedTuple):
+    """A chunk whose content isn't one of the message types in our schema."""
+
+    timestamp: int
+    client_id: int
+    tag: Optional[int]
+    """Field number of the content variant, or None if the chunk had no
+    recognisable content wrapper at all"""
+    bytes: bytes
+    """Raw wire-format encoding of the content (or the whole chunk if tag is None)"""
+
+
+def _wire_fields(data: bytes) -> Iterable[Tuple[int, Union[int, bytes]]]:
+    """Decode the top level of a wire-format protobuf message into
+    (field number, value) pairs, without needing a schema. Length-delimited
+    values are returned as bytes, everything else as ints."""
+    f = io.BytesIO(data)
+    while (key := read_varint(f)) is not None:
+        field, wire_type = key >> 3, key & 0b111
+        if wire_type == 0:
+            value: Union[int, bytes] = read_varint(f)  # type: ignore
+        elif wire_type == 1:
+            value = int.from_bytes(f.read(8), "little")
+        elif wire_type == 2:
+            value = f.read(read_varint(f))  # type: ignore
+        elif wire_type == 5:
+            value = int.from_bytes(f.read(4), "little")
+        else:
+            raise ValueError(f"Unsupported wire type {wire_type} for field {field}")
+        yield field, value
+
+
+def unknown_chunk(chunk: pb.ReplayChunk, bytestring: bytes) -> UnknownChunk:
+    """Dig the raw content out of a chunk that our schema didn't recognise."""
+    tag, raw = None, bytestring
+    for field, inner in _wire_fields(bytestring):
+        if field == 3 and isinstance(inner, bytes):
+            for field, content in _wire_fields(inner):
+                if field == 1 and isinstance(content, bytes):
+                    for field, value in _wire_fields(content):
+                        if isinstance(value, bytes):
+                            tag, raw = field, value
+                            break
+    return UnknownChunk(
+        timestamp=chunk.timestamp, client_id=chunk.client_id, tag=tag, bytes=raw
+    )
+
+
+def iter_chunks(
+    replay: ReplaySource,
+    gzip_offset: Optional[int] = None,
+    recover: bool = False,
+) -> Iterable[Union[pb.ReplayChunk, UnknownChunk]]:
+    """Decode each chunk of a replay in turn. Chunks with content our schema
+    doesn't know about (including all in-game commands) come out as
+    `UnknownChunk`s holding the raw bytes, for reverse engineering."""
+    for offset, bytestring in split_replay_with_offsets(replay, gzip_offset, recover):
+        chunk = parse_chunk(bytestring, offset)
+        if chunk.inner.content.WhichOneof("content_type") is None:
+            yield unknown_chunk(chunk, bytestring)
+        else:
+            yield chunk
+
+
+INDEX_SUFFIX = ".idx"
+"""Suffix for chunk index sidecar files (see `load_chunk_index`)"""
+
+
+class ChunkIndexEntry(NamedTuple):
+    offset: int
+    """Offset of the chunk's length prefix within the decompressed stream"""
+    timestamp: int
+
+
+class ChunkIndex(BaseModel):
+    """Where each chunk of a replay lives in its decompressed stream, and
+    when it happened, so we can skip straight to a point in the game."""
+
+    replay_size: int
+    replay_mtime_ns: int
+    """Size and mtime of the replay when indexed, to detect stale indexes"""
+    entries: List[ChunkIndexEntry] = []
+
+    def is_current_for(self, replay: Path) -> bool:
+        stat = replay.stat()
+        return (self.replay_size, self.replay_mtime_ns) == (
+            stat.st_size,
+            stat.st_mtime_ns,
+        )
+
+
+def chunk_timestamp(chunk: bytes) -> int:
+    """Read just the timestamp of a wire-format chunk, without decoding the
+    rest of it (the timestamp comes first, so this is quick)."""
+    for field, value in _wire_fields(chunk):
+        if field == 1 and isinstance(value, int):
+            # (int32 fields encode negative values as 64-bit two's complement)
+            return value - (1 << 64) if value >> 63 else value
+    return 0
+
+
+def build_chunk_index(
+    replay: PathLike, gzip_offset: Optional[int] = None
+) -> ChunkIndex:
+    """Scan a replay and record the offset and timestamp of every chunk."""
+    replay = as_path(replay)
+    stat = replay.stat()
+    index = ChunkIndex(replay_size=stat.st_size, replay_mtime_ns=stat.st_mtime_ns)
+    with decompress(replay, gzip_offset) as f:
+        data = f.read()
+    prefix_start = 0
+    for frame in scan_chunk_frames(data):
+        chunk = data[frame.offset : frame.offset + frame.length]
+        index.entries.append(
+            ChunkIndexEntry(offset=prefix_start, timestamp=chunk_timestamp(chunk))
+        )
+        prefix_start = frame.offset + frame.length
+    return index
+
+
+class ReplayLengthInfo(NamedTuple):
+    chunks: int
+    last_timestamp: int
+    """Raw timestamp of the final chunk"""
+    last_timestamp_millis: float
+
+    @property
+    def duration_estimate_seconds(self) -> float:
+        """Rough length of the replay (including time spent in the lobby)."""
+        return self.last_timestamp_millis / 1000
+
+
+def replay_length_info(
+    replay: ReplaySource, gzip_offset: Optional[int] = None
+) -> ReplayLengthInfo:
+    """Count the chunks in a replay and find its final timestamp by walking
+    only the length-prefix framing - much faster than a full parse, for
+    sorting or filtering large libraries by length."""
+    with decompress(replay, gzip_offset) as f:
+        data = f.read()
+    frames = scan_chunk_frames(data)
+    last_timestamp = 0
+    if frames:
+        last = frames[-1]
+        last_timestamp = chunk_timestamp(data[last.offset : last.offset + last.length])
+    return ReplayLengthInfo(
+        chunks=len(frames),
+        last_timestamp=last_timestamp,
+        last_timestamp_millis=last_timestamp * REPLAY_TIMESTAMP_UNIT * 1000,
+    )
+
+
+def load_chunk_index(replay: PathLike, save: bool = True) -> ChunkIndex:
+    """Read the chunk index sidecar for a replay (e.g. foo.SGReplay.idx),
+    building it (and saving it, if `save` is set) if missing or out of date."""
+    replay = as_path(replay)
+    sidecar = replay.parent / f"{replay.name}{INDEX_SUFFIX}"
+    if sidecar.exists():
+        try:
+            index = ChunkIndex.model_validate_json(sidecar.read_bytes())
+        except ValueError as e:
+            logger.warning(f"Ignoring unreadable chunk index {sidecar}: {e}")
+        else:
+            if index.is_current_for(replay):
+                return index
+    index = build_chunk_index(replay)
+    if save:
+        sidecar.write_text(index.model_dump_json(), encoding="utf-8")
+    return index
+
+
+def seek_to_timestamp(
+    replay: PathLike, timestamp: float, index: Optional[ChunkIndex] = None
+) -> Iterable[bytes]:
+    """Like `split_replay`, but starting from the first chunk at or after the
+    given timestamp (in replay units, not seconds).
+
+    The stream still has to be decompressed from the start, but the chunks
+    before the timestamp are skipped without being split or decoded."""
+    replay = as_path(replay)
+    if index is None:
+        index = load_chunk_index(replay)
+    position = bisect_left([e.timestamp for e in index.entries], timestamp)
+    if position == len(index.entries):
+        return
+    with decompress(replay) as f:
+        f.seek(index.entries[position].offset)
+        while (length := read_varint(f)) is not None:
             yield f.read(length)
 
 
+def minimize_replay(replay: PathLike, out_path: PathLike) -> int:
+    """Write a copy of a replay containing only the chunks we know how to
+    interpret (lobby and lifecycle messages), dropping all in-game commands.
+
+    The result is tiny but renames the same way as the original, which makes it
+    ideal for attaching to bug reports. Returns the number of chunks kept."""
+    replay = as_path(replay)
+    out_path = as_path(out_path)
+    layout = detect_header_layout(replay)
+    with replay.open("rb") as f:
+        header = f.read(layout.gzip_offset)
+    kept = 0
+    with out_path.open("wb") as out:
+        out.write(header)
+        with gzip.GzipFile(fileobj=out, mode="wb") as gz:
+            for offset, bytestring in split_replay_with_offsets(replay):
+                chunk = parse_chunk(bytestring, offset)
+                if chunk.inner.content.WhichOneof("content_type") is None:
+                    continue
+                write_varint(gz, len(bytestring))
+                gz.write(bytestring)
+                kept += 1
+    # Keep the original timestamps, since the game sorts replays by mtime:
+    stat = replay.stat()
+    os.utime(out_path, ns=(stat.st_atime_ns, stat.st_mtime_ns))
+    return kept
+
+
+def describe_schema() -> Dict[str, Dict[str, int]]:
+    """Describe what the bundled protobuf schema knows about.
+
+    Returns a mapping from the fully-qualified name of each message and enum
+    type to its field (or value) names and numbers."""
+    schema: Dict[str, Dict[str, int]] = {}
+
+    def visit(desc):
+        schema[desc.full_name] = {f.name: f.number for f in desc.fields}
+        for enum in desc.enum_types:
+            schema[enum.full_name] = {v.name: v.number for v in enum.values}
+        for nested in desc.nested_types:
+            visit(nested)
+
+    for desc in pb.DESCRIPTOR.message_types_by_name.values():
+        visit(desc)
+    for enum in pb.DESCRIPTOR.enum_types_by_name.values():
+        schema[enum.full_name] = {v.name: v.number for v in enum.values}
+    return schema
+
+
 class Spectator(BaseModel):
     nickname: str
     nickname_discriminator: Optional[str] = None
@@ -89,6 +788,17 @@ class Player(BaseModel):
     is_ai: bool = False
     disconnect_time: Optional[float] = None
     leave_reason: str = "unknown"
+    load_time_seconds: Optional[float] = None
+    faction_history: List[Tuple[Optional[float], str]] = []
+    """Every faction picked in the lobby, as (game time, faction) pairs. Lobby
+    picks happen before the game starts, so the times are negative."""
+    left_early: bool = False
+    """Whether this player left within the grace period (see
+    `Client.left_early`)"""
+    display_name: Optional[str] = None
+    """For AIs, a canonical name to use in place of a nickname (see
+    `Slot.display_name`)"""
+    slot_number: Optional[int] = None
 
 
 class ReplaySummary(BaseModel):
@@ -98,49 +808,211 @@ class ReplaySummary(BaseModel):
     spectators: List[Spectator] = []
     duration_seconds: Optional[float] = None
     is_1v1_ladder_game: bool = False
+    match_type: str = "1v1"
+    """Name of the match type (see `MATCH_TYPES`), e.g. 1v1 or campaign"""
+    matchup: str = ""
+    """Canonical matchup string (see `format_matchup`)"""
+    mod_info: Optional[ModInfo] = None
+    """Set for arcade games played on workshop maps"""
+    is_mirror: bool = False
+    compressed_bytes: Optional[int] = None
+    """Size of the replay's gzipped data (i.e. the file minus its header)"""
+    decompressed_bytes: Optional[int] = None
+    """Size of the replay's decompressed message stream"""
+
+    def __str__(self):
+        return format_summary(self)
+
+    @property
+    def compression_ratio(self) -> Optional[float]:
+        """How many times smaller the replay is for being gzipped"""
+        if not self.compressed_bytes or self.decompressed_bytes is None:
+            return None
+        return self.decompressed_bytes / self.compressed_bytes
+
+    def perspective(
+        self, my_uuids: Sequence[UUID]
+    ) -> Tuple[Optional[Player], Optional[Player]]:
+        """Find (us, them) from the point of view of a user who owns the given
+        accounts, in order of preference (so if two of your accounts played
+        each other, the first one listed is "us").
+
+        `them` is only populated in games with exactly two players."""
+        for uuid in my_uuids:
+            for i, player in enumerate(self.players):
+                if player.uuid == uuid:
+                    them = None
+                    if len(self.players) == 2:
+                        them = self.players[1 - i]
+                    return player, them
+        return None, None
+
+
+class ResultConfidence(str, Enum):
+    """How sure we are of a game's result."""
+
+    explicit = "explicit"
+    """The replay says so (i.e. someone surrendered)"""
+    inferred = "inferred"
+    """Guessed from who disconnected first"""
+    unknown = "unknown"
+    """We have no idea"""
+
+    def at_least(self, other: ResultConfidence) -> bool:
+        # Members are declared from most to least confident:
+        order = list(ResultConfidence)
+        return order.index(self) <= order.index(other)
+
+
+def judge_result(
+    us: Player, them: Player, build_number: int
+) -> Tuple[Optional[str], ResultConfidence]:
+    """Determine the result ("win", "loss" or None if unknown) of a 1v1 game
+    from the perspective of `us`, and how confident we are in it."""
+    if build_number >= FRIGATE:
+        # Since Frigate we've had explicit surrender messages, so we rely on them alone for certainty:
+        if us.leave_reason == "surrender":
+            return "loss", ResultConfidence.explicit
+        if them.leave_reason == "surrender":
+            return "win", ResultConfidence.explicit
+    else:
+        # For old replays, best we can do is guess based on disconnection times:
+        t1 = us.disconnect_time
+        t2 = them.disconnect_time
+        if t1 and t2:
+            return ("win" if t1 > t2 else "loss"), ResultConfidence.inferred
+        elif t1:
+            return "loss", ResultConfidence.inferred
+        elif t2:
+            return "win", ResultConfidence.inferred
+    return None, ResultConfidence.unknown
 
 
-def summarize_replay(replay: Union[Path, BinaryIO]) -> ReplaySummary:
-    """Parse what we can from a stormgate replay."""
-    build_number = get_build_number(replay)
-    state = GameState.at_end_of(replay)
+def infer_result(us: Player, them: Player, build_number: int) -> Optional[str]:
+    """Determine the result ("win", "loss" or None if unknown) of a 1v1 game
+    from the perspective of `us`."""
+    return judge_result(us, them, build_number)[0]
+
+
+def summarize_replay(
+    replay: ReplaySource,
+    gzip_offset: Optional[int] = None,
+    recover: bool = False,
+    max_chunks: Optional[int] = None,
+    max_seconds: Optional[float] = None,
+    early_leave_seconds: float = EARLY_LEAVE_SECONDS,
+    max_bytes: Optional[int] = None,
+) -> ReplaySummary:
+    """Parse what we can from a stormgate replay (see `GameState.all_games_in`
+    for the meaning of the limits, and `GameState.mark_early_leavers` for
+    `early_leave_seconds`)."""
+    state = GameState.at_end_of(
+        replay,
+        gzip_offset,
+        recover=recover,
+        max_chunks=max_chunks,
+        max_seconds=max_seconds,
+        max_bytes=max_bytes,
+    )
+    return summarize_state(state, early_leave_seconds)
+
+
+def simulate_all(
+    replay: ReplaySource,
+    gzip_offset: Optional[int] = None,
+    recover: bool = False,
+    max_chunks: Optional[int] = None,
+    max_seconds: Optional[float] = None,
+    max_bytes: Optional[int] = None,
+) -> List[GameState]:
+    """Simulate a replay, returning one end state for each game in it - a
+    single replay file can hold several back-to-back games when a custom
+    lobby is reused (see `GameState.all_games_in`)."""
+    return GameState.all_games_in(
+        replay,
+        gzip_offset,
+        recover=recover,
+        max_chunks=max_chunks,
+        max_seconds=max_seconds,
+        max_bytes=max_bytes,
+    )
+
+
+def summarize_all(
+    replay: ReplaySource,
+    gzip_offset: Optional[int] = None,
+    recover: bool = False,
+    max_chunks: Optional[int] = None,
+    max_seconds: Optional[float] = None,
+    early_leave_seconds: float = EARLY_LEAVE_SECONDS,
+    max_bytes: Optional[int] = None,
+) -> List[ReplaySummary]:
+    """As `summarize_replay`, but with a summary of each game in the replay
+    (see `simulate_all`) rather than just the last."""
+    states = simulate_all(
+        replay,
+        gzip_offset,
+        recover=recover,
+        max_chunks=max_chunks,
+        max_seconds=max_seconds,
+        max_bytes=max_bytes,
+    )
+    return [summarize_state(state, early_leave_seconds) for state in states]
+
+
+def summarize_state(
+    state: GameState, early_leave_seconds: Optional[float] = None
+) -> ReplaySummary:
+    """Condense the end state of a replay simulation into a ReplaySummary.
+
+    Early leavers are (re)marked here, using `early_leave_seconds` if given
+    or else the state's own grace period (see `GameState.mark_early_leavers`),
+    so that every summary of a game agrees on `Player.left_early`."""
+    state.mark_early_leavers(early_leave_seconds)
     info = ReplaySummary(
-        build_number=build_number,
+        build_number=state.build_number or 0,
         map_name=state.map_name,
+        match_type=state.match_type.name,
+        matchup=state.matchup,
+        mod_info=state.mod_info,
+        is_mirror=state.is_mirror,
+        compressed_bytes=state.compressed_bytes,
+        decompressed_bytes=state.decompressed_bytes,
     )
-    if state.game_started_time is not None:
-        first_left_game_time = min(
-            c.left_game_time or float("inf") for c in state.clients.values()
-        )
-        info.duration_seconds = (
-            first_left_game_time - state.game_started_time
-        ) * REPLAY_TIMESTAMP_UNIT
-    for slot in state.slots.values():
-        if slot.ai_type is not None:
-            info.players.append(
-                Player(
-                    nickname=slot.ai_type.name,
-                    is_ai=True,
-                    faction=slot.faction.name,
-                )
+    left_game_times = [
+        c.left_game_time for c in state.clients.values() if c.left_game_time is not None
+    ]
+    if left_game_times:
+        info.duration_seconds = state.game_time(min(left_game_times))
+    seated_clients = set()
+    for sp in state.slot_players():
+        info.players.append(
+            p := Player(
+                nickname=sp.nickname,
+                is_ai=sp.is_ai,
+                faction=sp.faction.name,
+                display_name=sp.slot.display_name,
+                slot_number=sp.slot_number,
             )
-        elif slot.client_id is not None:
-            client = state.clients.pop(slot.client_id)
-            info.players.append(
-                p := Player(
-                    nickname=client.nickname,
-                    nickname_discriminator=client.discriminator,
-                    uuid=client.uuid,
-                    is_ai=False,
-                    faction=slot.faction.name,
-                )
-            )
-            if state.game_started_time is not None and client.left_game_time is not None:
-                p.disconnect_time = (client.left_game_time - state.game_started_time)*REPLAY_TIMESTAMP_UNIT
-            p.leave_reason = client.left_game_reason.name
-    for client in state.clients.values():
-        if client.slot_number != 255:
-            raise ReplayParsingError("Player not in a slot but slot_number != 255?")
+        )
+        p.faction_history = [
+            (state.game_time(c.timestamp), c.faction.name)
+            for c in state.faction_history(sp)
+        ]
+        if sp.client is not None:
+            seated_clients.add(sp.client.client_id)
+            p.nickname_discriminator = sp.client.discriminator
+            p.uuid = sp.client.uuid
+            if sp.left_game_time is not None:
+                p.disconnect_time = state.game_time(sp.left_game_time)
+            p.leave_reason = sp.left_game_reason.name
+            p.left_early = sp.client.left_early
+            p.load_time_seconds = sp.client.load_time_seconds
+    for client_id, client in state.clients.items():
+        if client_id in seated_clients:
+            continue
+        if not client.is_spectator:
+            raise ReplayParsingError("Player not in a slot but not a spectator?")
         info.spectators.append(
             Spectator(
                 nickname=client.nickname,
@@ -152,19 +1024,163 @@ def summarize_replay(replay: Union[Path, BinaryIO]) -> ReplaySummary:
     return info
 
 
+DurationStyle = Literal["compact", "clock", "seconds"]
+
+
+def format_duration(seconds: Optional[float], style: DurationStyle = "compact") -> str:
+    """Format a game duration in one of the following styles:
+
+    * compact: 12m03s
+    * clock: 12:03 (or 1:02:03 for games over an hour)
+    * seconds: 723s
+
+    Partial seconds are always truncated, so that all styles agree."""
+    if seconds is None:
+        return ""
+    total = int(seconds)
+    if style == "seconds":
+        return f"{total}s"
+    minutes, secs = divmod(total, 60)
+    if style == "clock":
+        hours, minutes = divmod(minutes, 60)
+        if hours:
+            return f"{hours}:{minutes:02d}:{secs:02d}"
+        return f"{minutes}:{secs:02d}"
+    return f"{minutes:02d}m{secs:02d}s"
+
+
+def format_summary(info: ReplaySummary) -> str:
+    """Produce a compact human-readable description of a match."""
+    lines = [f"{info.map_name or 'Unknown map'} (build {info.build_number})"]
+    if info.duration_seconds is not None:
+        lines.append(f"Duration: {format_duration(info.duration_seconds)}")
+    names = disambiguate_nicknames(
+        [p.nickname for p in info.players],
+        [p.nickname_discriminator for p in info.players],
+        [p.slot_number for p in info.players],
+    )
+    for player, name in zip(info.players, names):
+        line = f"  {name} ({(player.faction or 'unknown').capitalize()})"
+        if player.is_ai:
+            line += " [AI]"
+        elif player.leave_reason != "unknown":
+            line += f" - {player.leave_reason}"
+            if player.disconnect_time is not None:
+                line += f" at {format_duration(player.disconnect_time)}"
+        lines.append(line)
+    if info.spectators:
+        lines.append("Spectators: " + ", ".join(s.nickname for s in info.spectators))
+    return "\n".join(lines)
+
+
+class MatchTypeInfo(NamedTuple):
+    """The shape of a kind of match: how many player slots there are, and
+    how they're split into teams (in slot order)."""
+
+    name: str
+    team_sizes: Tuple[int, ...]
+
+    @property
+    def slot_count(self) -> int:
+        return sum(self.team_sizes)
+
+    def team_of_slot(self, slot_number: int) -> Optional[int]:
+        """Index of the team a (1-based) slot belongs to, if it's a player slot."""
+        first = 1
+        for team, size in enumerate(self.team_sizes):
+            if first <= slot_number < first + size:
+                return team
+            first += size
+        return None
+
+
 # Unfortunately, to correctly determine who's in player slots and who's in
-# spectator slots we need to know how many players the map has.
-# For now, this is just 2 unless it's WreckHavoc.
-player_slot_count: Dict[str, int] = defaultdict(lambda: 2, {"WreckHavoc": 3,"TheAbyssalGates" : 3})
+# spectator slots we need to know how many players the map has. Adding a new
+# mode or map should just be a matter of editing these tables.
+MATCH_TYPES: Dict[str, MatchTypeInfo] = {
+    info.name: info
+    for info in [
+        MatchTypeInfo("1v1", (1, 1)),
+        MatchTypeInfo("coop", (3,)),
+        MatchTypeInfo("campaign", (1,)),
+    ]
+}
+"""Known match types, by name"""
+
+MAP_MATCH_TYPES: Dict[str, str] = {
+    "WreckHavoc": "coop",
+    "TheAbyssalGates": "coop",
+}
+"""Match type of each map that isn't DEFAULT_MATCH_TYPE"""
+
+DEFAULT_MATCH_TYPE = "1v1"
+
+
+CAMPAIGN_FOLDER_MARKER = "campaign"
+"""Campaign missions are loaded from a map folder containing this"""
+
+
+def match_type_info(
+    map_name: Optional[str] = None, map_folder: Optional[str] = None
+) -> MatchTypeInfo:
+    """Look up the match type played on a map."""
+    if map_folder and CAMPAIGN_FOLDER_MARKER in map_folder.lower():
+        return MATCH_TYPES["campaign"]
+    return MATCH_TYPES[MAP_MATCH_TYPES.get(map_name or "", DEFAULT_MATCH_TYPE)]
+
+
+WORKSHOP_ID_PATTERN = re.compile(
+    r"(?:^|[\\/])(?:workshop|mods?)[\\/]"
+    r"(?:content[\\/]\d+[\\/])?"  # Steam's workshop/content/<app id>/ layout
+    r"(\d{6,})(?:[\\/]|$)",
+    re.IGNORECASE,
+)
+"""Workshop item IDs are long numbers, given as the folder directly under a
+workshop (or mods) folder in the map's path. Other long numbers in map
+folders (dates, build numbers) aren't IDs."""
+
 
+class ModInfo(BaseModel):
+    """Where a custom (arcade/mod) map came from."""
 
-class SlotType(IntEnum):
+    folder: str
+    """Folder the map was loaded from"""
+    workshop_id: Optional[str] = None
+
+
+def mod_info(map_folder: Optional[str]) -> Optional[ModInfo]:
+    """Work out whether a map folder belongs to a workshop mod, and if so
+    which one. Returns None for built-in maps."""
+    if not map_folder:
+        return None
+    if (m := WORKSHOP_ID_PATTERN.search(map_folder)) is None:
+        return None
+    return ModInfo(folder=map_folder, workshop_id=m.group(1))
+
+
+# Kept for backwards compatibility - use match_type_info instead.
+player_slot_count: Dict[str, int] = defaultdict(
+    lambda: match_type_info().slot_count,
+    {name: match_type_info(name).slot_count for name in MAP_MATCH_TYPES},
+)
+
+
+class CheckedIntEnum(IntEnum):
+    """IntEnum with a lookup that returns None for unknown values, for use on
+    raw values straight out of a replay."""
+
+    @classmethod
+    def from_value(cls, value: int):
+        return cls._value2member_map_.get(value)
+
+
+class SlotType(CheckedIntEnum):
     closed = 0
     human = 1
     ai = 2
 
 
-class Faction(IntEnum):
+class Faction(CheckedIntEnum):
     vanguard = 0
     infernals = 1
     catgirls = 2
@@ -172,103 +1188,1195 @@ class Faction(IntEnum):
     maloc = 201
 
 
-class AIType(IntEnum):
+FACTION_LETTERS = {
+    Faction.vanguard: "V",
+    Faction.infernals: "I",
+    Faction.catgirls: "C",
+    Faction.blockade: "B",
+    Faction.maloc: "M",
+}
+"""Single-letter abbreviations used in matchup strings (catgirls are the
+Celestial Armada)"""
+
+
+def format_matchup(teams: Sequence[Sequence[Faction]]) -> str:
+    """Build a canonical matchup string from the factions on each team.
+
+    Factions within a team and the teams themselves are sorted, so the same
+    matchup always gives the same string regardless of slot order: two
+    single-player teams give e.g. "VvI", anything else e.g. "2v2: VC vs II"."""
+    sorted_teams = sorted(
+        (sorted(team) for team in teams if team), key=lambda t: (-len(t), t)
+    )
+    words = ["".join(FACTION_LETTERS[f] for f in team) for team in sorted_teams]
+    if len(words) == 2 and all(len(team) == 1 for team in sorted_teams):
+        return "v".join(words)
+    sizes = "v".join(str(len(team)) for team in sorted_teams)
+    return f"{sizes}: {' vs '.join(words)}"
+
+
+class AIType(CheckedIntEnum):
+    Unknown = -1
     PeacefulBot = 0
     MurderBotJr = 1
     MurderBotSr = 2
 
+    @classmethod
+    def _missing_(cls, value):
+        # New bot tiers shouldn't break parsing; the raw value is kept in
+        # Slot.ai_type_value.
+        logger.warning(f"Unknown AI type {value}")
+        return cls.Unknown
+
+
+AI_DISPLAY_NAMES = {
+    AIType.PeacefulBot: "Peaceful Bot",
+    AIType.MurderBotJr: "MurderBot Jr",
+    AIType.MurderBotSr: "MurderBot Sr",
+}
+"""Human-readable names for each AI type, as shown in-game"""
+
+
+TEAM_VARIABLE_ID: Optional[int] = None
+"""Lobby variable holding each slot's team. Nobody has identified this yet -
+if you find it (see `Slot.variables`), set it here."""
+
+
+class LobbyVariable(str, Enum):
+    """The slot settings we know how to interpret."""
+
+    slot_type = "slot_type"
+    faction = "faction"
+    ai_type = "ai_type"
+    team = "team"
+    color = "color"
+
+
+LOBBY_VARIABLES: Dict[int, LobbyVariable] = {
+    374945738: LobbyVariable.slot_type,
+    2952722564: LobbyVariable.faction,
+    655515685: LobbyVariable.ai_type,
+}
+"""What each lobby variable ID means, on the standard maps"""
+
+MAP_VARIABLE_OVERRIDES: Dict[str, Dict[int, Optional[LobbyVariable]]] = {}
+"""Variable IDs for custom maps (e.g. community observer maps) that lay out
+their slot settings differently, by map name. These take precedence over
+LOBBY_VARIABLES; map an ID to None to treat it as unknown on that map."""
+
+
+def lobby_variable(
+    map_name: Optional[str], variable_id: int
+) -> Optional[LobbyVariable]:
+    """Look up what a lobby variable means on a given map."""
+    overrides = MAP_VARIABLE_OVERRIDES.get(map_name or "", {})
+    if variable_id in overrides:
+        return overrides[variable_id]
+    if variable_id == TEAM_VARIABLE_ID:
+        return LobbyVariable.team
+    return LOBBY_VARIABLES.get(variable_id)
+
 
 class Slot(BaseModel):
     type: SlotType = SlotType.human
     faction: Faction = Faction(0)
     ai_type: Optional[AIType] = None
+    ai_type_value: Optional[int] = None
+    """Raw AI type variable value, in case it's one we don't recognise"""
     client_id: Optional[int] = None
+    team_value: Optional[int] = None
+    """Raw team variable value (see `team`)"""
+    color: Optional[int] = None
+    """Raw player colour variable value, on maps where we know which it is"""
+    variables: Dict[int, int] = {}
+    """Lobby variables set on this slot that we don't understand yet"""
+
+    @property
+    def team(self) -> Optional[int]:
+        """Team this slot was assigned to in the lobby, if known."""
+        if self.team_value is not None:
+            return self.team_value
+        if TEAM_VARIABLE_ID is None:
+            return None
+        return self.variables.get(TEAM_VARIABLE_ID)
+
+    def __str__(self):
+        if self.ai_type is not None:
+            return self.display_name
+        faction = self.faction.name.capitalize()
+        if self.client_id is not None:
+            return f"{self.type.name} client {self.client_id} ({faction})"
+        return f"{self.type.name} ({faction})"
+
+    @property
+    def ai_display_name(self) -> Optional[str]:
+        """Human-readable name of the AI in this slot, if any."""
+        if self.ai_type is None:
+            return None
+        if self.ai_type == AIType.Unknown:
+            return f"Unknown AI {self.ai_type_value}"
+        return AI_DISPLAY_NAMES[self.ai_type]
+
+    @property
+    def display_name(self) -> Optional[str]:
+        """Name for the AI in this slot and its faction, e.g. MurderBot Sr
+        (Infernals), since AIs don't have nicknames. None for slots not
+        occupied by an AI."""
+        if self.ai_type is None:
+            return None
+        return f"{self.ai_display_name} ({self.faction.name.capitalize()})"
 
 
 # The python protobuf bindings don't use standard python enums - they just return ints.
 # I want nice type hints so I'm just going to maintain this manually.
-class LeftGameReason(IntEnum):
+class LeftGameReason(CheckedIntEnum):
     unknown = 0
     surrender = 1
     leave = 2
 
 
+class Team(BaseModel):
+    """A group of slots playing together."""
+
+    id: int
+    """Index of the team within the game (not the in-game team number)"""
+    slots: List[int] = []
+    result: Optional[Literal["win", "loss"]] = None
+    """Outcome for this team, if we can tell"""
+    result_confidence: ResultConfidence = ResultConfidence.unknown
+
+
+SPECTATOR_SLOT = 255
+"""Slot number used in replays for clients who are spectating"""
+
+SeatKind = Literal["slot", "spectator", "unassigned"]
+
+
+class Seat(NamedTuple):
+    """Where a client is sitting: a player slot, the spectator area, or
+    nowhere yet."""
+
+    kind: SeatKind
+    slot_number: Optional[int] = None
+    """The player slot number, for seats of kind "slot" only"""
+
+    @staticmethod
+    def from_slot_number(slot_number: Optional[int]) -> Seat:
+        if slot_number is None:
+            return Seat("unassigned")
+        if slot_number == SPECTATOR_SLOT:
+            return Seat("spectator")
+        return Seat("slot", slot_number)
+
+
 class Client(BaseModel):
     uuid: UUID
     client_id: int
     nickname: str
     discriminator: str
-    slot_number: Optional[int] = None  # 255 means spectator
+    slot_number: Optional[int] = None
+    """Raw slot number (SPECTATOR_SLOT for spectators) - see also `seat`"""
     left_game_time: Optional[float] = None
     left_game_reason: LeftGameReason = LeftGameReason.unknown
+    left_early: bool = False
+    """Whether this client left the game before `GameState.early_leave_seconds`
+    of game time had passed"""
+    is_placeholder: bool = False
+    """Whether this client was never introduced by a Player message, so its
+    identity is unknown."""
+    other_nicknames: List[str] = []
+    """Other nicknames seen for this client (from the matchmaking slot
+    assignment or an earlier Player message) that disagreed with `nickname`."""
+    command_count: int = 0
+    """Number of chunks sent by this client that aren't lobby/lifecycle
+    messages we understand - roughly, the number of in-game commands."""
+    bytes_sent: int = 0
+    """Total encoded size of all chunks sent by this client."""
+    start_game_time: Optional[float] = None
+    """Timestamp at which this client sent StartGame (i.e. finished loading)"""
+    load_time_seconds: Optional[float] = None
+    """Time from the lobby locking to this client finishing loading"""
+    activity: List[int] = []
+    """Number of commands sent in each ACTIVITY_BUCKET_SECONDS-long interval
+    since the start of the game."""
+    bursts: int = 0
+    """Number of bursts of in-game commands, i.e. runs of commands sent less
+    than BURST_GAP_SECONDS apart (e.g. queueing up orders)"""
+    idle_seconds: float = 0.0
+    """Total game time spent idle between bursts"""
+    longest_idle_seconds: float = 0.0
+    last_command_time: Optional[float] = None
+    """Game time of the most recent command"""
+    command_usage: Dict[int, int] = {}
+    """Number of commands sent of each type (by content field number, as we
+    don't know most of their names) - only filled in when simulating with the
+    `track_command_usage` handler."""
+
+    def __str__(self):
+        name = self.nickname
+        if self.discriminator:
+            name += f"#{self.discriminator}"
+        seat = self.seat
+        where = f"slot {seat.slot_number}" if seat.kind == "slot" else seat.kind
+        return f"{name} (client {self.client_id}, {where})"
+
+    @property
+    def seat(self) -> Seat:
+        return Seat.from_slot_number(self.slot_number)
+
+    @property
+    def is_spectator(self) -> bool:
+        return self.slot_number == SPECTATOR_SLOT
+
+    @property
+    def mean_burst_size(self) -> Optional[float]:
+        """Average number of commands per burst (see `bursts`)"""
+        return sum(self.activity) / self.bursts if self.bursts else None
+
+    @property
+    def mean_idle_seconds(self) -> Optional[float]:
+        """Average gap between bursts (see `bursts`)"""
+        return self.idle_seconds / (self.bursts - 1) if self.bursts > 1 else None
+
+    def record_command(self, game_time: Optional[float]):
+        """Count a command sent at the given number of seconds into the game
+        (or None if the game hasn't started yet)."""
+        self.command_count += 1
+        if game_time is None or game_time < 0:
+            return
+        last, self.last_command_time = self.last_command_time, game_time
+        if last is None:
+            self.bursts += 1
+        elif (gap := game_time - last) >= BURST_GAP_SECONDS:
+            self.bursts += 1
+            self.idle_seconds += gap
+            self.longest_idle_seconds = max(self.longest_idle_seconds, gap)
+        bucket = int(game_time // ACTIVITY_BUCKET_SECONDS)
+        if len(self.activity) <= bucket:
+            self.activity.extend([0] * (bucket + 1 - len(self.activity)))
+        self.activity[bucket] += 1
+
+
+class TimestampOrdered:
+    """Mixin ordering lobby/game events by when they happened, so that
+    e.g. slot and faction changes can be merged into one sorted timeline."""
+
+    def __lt__(self, other: TimestampOrdered) -> bool:
+        return self.timestamp < other.timestamp
+
+    def __le__(self, other: TimestampOrdered) -> bool:
+        return self.timestamp <= other.timestamp
+
+    def __gt__(self, other: TimestampOrdered) -> bool:
+        return self.timestamp > other.timestamp
+
+    def __ge__(self, other: TimestampOrdered) -> bool:
+        return self.timestamp >= other.timestamp
+
+
+class NumberedSlot(NamedTuple):
+    """A slot along with its number (see `GameState.slot_list`)."""
+
+    slot_number: int
+    slot: Slot
+
+
+class SlotPlayer(BaseModel):
+    """An occupied player slot, joined with the client (if any) in it."""
+
+    slot_number: int
+    slot: Slot
+    client: Optional[Client] = None
+
+    @property
+    def is_ai(self) -> bool:
+        return self.slot.ai_type is not None
+
+    @property
+    def nickname(self) -> str:
+        if self.slot.ai_type is not None:
+            return self.slot.ai_type.name
+        assert self.client is not None
+        return self.client.nickname
+
+    @property
+    def display_name(self) -> str:
+        """As `nickname`, but with AIs named as they are in-game (see
+        `Slot.ai_display_name`)."""
+        if self.slot.ai_type is not None:
+            return self.slot.ai_display_name  # type: ignore
+        return self.nickname
+
+    @property
+    def faction(self) -> Faction:
+        return self.slot.faction
+
+    @property
+    def left_game_time(self) -> Optional[float]:
+        return self.client.left_game_time if self.client else None
+
+    @property
+    def left_game_reason(self) -> LeftGameReason:
+        return self.client.left_game_reason if self.client else LeftGameReason.unknown
+
+
+class SlotChange(TimestampOrdered, BaseModel):
+    """A lobby event in which a client moved between slots."""
+
+    timestamp: float
+    client_id: int
+    from_slot: Optional[int] = None
+    to_slot: int
+    displaced_client_id: Optional[int] = None
+    """Client who was already in `to_slot` and got moved out of the way."""
+
+
+class FactionChange(TimestampOrdered, BaseModel):
+    """A lobby event in which the faction for a slot was changed."""
+
+    timestamp: float
+    slot_number: int
+    client_id: Optional[int] = None
+    """Client occupying the slot at the time, or None for AI/empty slots."""
+    faction: Faction
+
+
+def uuid_from_path(replay_path: PathLike) -> Optional[UUID]:
+    """Find the UUID of the account that recorded a replay, based on the
+    folder it's stored in (Replays/<uuid>/...)."""
+    for part in reversed(as_path(replay_path).parts):
+        try:
+            return UUID(hex=part)
+        except ValueError:
+            pass
+    return None
 
 
 def parse_uuid(uuid: pb.UUID) -> UUID:
-    return UUID(bytes=struct.pack(">qq", uuid.part1, uuid.part2))
+    return _uuid_from_parts(uuid.part1, uuid.part2)
+
+
+@lru_cache(maxsize=4096)
+def _uuid_from_parts(part1: int, part2: int) -> UUID:
+    # The same handful of accounts turn up over and over in a replay library,
+    # so it's worth caching these rather than rebuilding them every time.
+    return UUID(bytes=struct.pack(">qq", part1, part2))
+
+
+def format_uuid(uuid: pb.UUID) -> str:
+    """Format a protobuf UUID as a hyphenated hex string, without going via
+    the uuid module."""
+    h = struct.pack(">qq", uuid.part1, uuid.part2).hex()
+    return f"{h[:8]}-{h[8:12]}-{h[12:16]}-{h[16:20]}-{h[20:]}"
+
+
+def uuid_parts(uuid: Union[UUID, str]) -> Tuple[int, int]:
+    """Split a UUID (or its string form, with or without hyphens) into the
+    two signed 64-bit halves used in replays - the inverse of `parse_uuid`."""
+    if isinstance(uuid, UUID):
+        raw = uuid.bytes
+    else:
+        raw = bytes.fromhex(uuid.strip("{}").replace("-", ""))
+        if len(raw) != 16:
+            raise ValueError(f"Not a UUID: {uuid!r}")
+    part1, part2 = struct.unpack(">qq", raw)
+    return part1, part2
+
+
+class MapLoaded(NamedTuple):
+    timestamp: int
+    client_id: int
+    name: str
+    folder: str
+    seed: int
+
+
+class SlotAssigned(NamedTuple):
+    """The matchmaking server reserving a slot for a player."""
+
+    timestamp: int
+    client_id: int
+    uuid: UUID
+    slot_number: int
+    nickname: str
+
+
+class PlayerJoined(NamedTuple):
+    timestamp: int
+    client_id: int
+    uuid: UUID
+    nickname: str
+    discriminator: str
+
+
+class SlotChanged(NamedTuple):
+    timestamp: int
+    client_id: int
+    slot_number: Optional[int]
+    """Slot the client asked for, or None for the first free one"""
+
+
+class VariableSet(NamedTuple):
+    timestamp: int
+    client_id: int
+    slot_number: int
+    variable_id: int
+    value: int
+
+
+class GameStarted(NamedTuple):
+    """A client finishing loading into the game."""
+
+    timestamp: int
+    client_id: int
+
+
+class PlayerLeft(NamedTuple):
+    timestamp: int
+    client_id: int
+    reason: int
+    """Raw reason value (see `LeftGameReason`)"""
+
+
+class UnknownEvent(NamedTuple):
+    """A chunk we don't understand - usually an in-game command."""
+
+    timestamp: int
+    client_id: int
+    raw: Optional[UnknownChunk] = None
+    """Raw content of the chunk, if it was asked for (see `decode_event`)"""
+
+
+ReplayEvent = Union[
+    MapLoaded,
+    SlotAssigned,
+    PlayerJoined,
+    SlotChanged,
+    VariableSet,
+    GameStarted,
+    PlayerLeft,
+    UnknownEvent,
+]
+"""Everything that can happen in a replay, as far as the simulation is
+concerned. Chat messages haven't been identified yet, so they come out as
+UnknownEvents."""
+
+
+CONTENT_TYPES: Tuple[str, ...] = tuple(
+    field.name
+    for field in pb.ReplayChunk.Wrapper.ReplayContent.DESCRIPTOR.oneofs_by_name[
+        "content_type"
+    ].fields
+)
+"""Every variant of the chunk content oneof in our schema"""
+
+EventDecoder = Callable[[Any, int, int], ReplayEvent]
+"""Turns the message for one content type (along with the chunk's timestamp
+and client ID) into an event"""
+
+event_decoders: Dict[str, EventDecoder] = {}
+"""Decoder for each content type, as registered by `event_decoder`. Content
+types without one come out as `UnknownEvent`s."""
+
+
+def event_decoder(content_type: str) -> Callable[[EventDecoder], EventDecoder]:
+    """Decorator registering the decoder for a content type - adding support
+    for a new message is a matter of adding it to the schema, writing a
+    decoder for it and a `GameState` handler for the resulting event."""
+    if content_type not in CONTENT_TYPES:
+        raise ValueError(f"{content_type} isn't a content type in the schema")
+
+    def register(decoder: EventDecoder) -> EventDecoder:
+        event_decoders[content_type] = decoder
+        return decoder
+
+    return register
+
+
+@event_decoder("map")
+def decode_map(msg: pb.Map, timestamp: int, client_id: int) -> MapLoaded:
+    return MapLoaded(timestamp, client_id, msg.name, msg.folder, msg.seed)
+
+
+@event_decoder("assign_player_slot")
+def decode_assign_player_slot(
+    msg: pb.AssignPlayerSlot, timestamp: int, client_id: int
+) -> SlotAssigned:
+    return SlotAssigned(
+        timestamp, client_id, parse_uuid(msg.uuid), msg.slot, msg.nickname
+    )
+
+
+@event_decoder("player")
+def decode_player(msg: pb.Player, timestamp: int, client_id: int) -> PlayerJoined:
+    return PlayerJoined(
+        timestamp,
+        client_id,
+        parse_uuid(msg.uuid),
+        msg.name.nickname,
+        msg.name.discriminator,
+    )
+
+
+@event_decoder("change_slot")
+def decode_change_slot(
+    msg: pb.LobbyChangeSlot, timestamp: int, client_id: int
+) -> SlotChanged:
+    choice = msg.choice
+    slot_number = (
+        choice.specific_slot.slot
+        if choice.WhichOneof("choice_type") == "specific_slot"
+        else None
+    )
+    return SlotChanged(timestamp, client_id, slot_number)
+
+
+@event_decoder("set_variable")
+def decode_set_variable(
+    msg: pb.LobbySetVariable, timestamp: int, client_id: int
+) -> VariableSet:
+    return VariableSet(timestamp, client_id, msg.slot, msg.variable_id, msg.value)
+
+
+@event_decoder("start_game")
+def decode_start_game(msg: pb.StartGame, timestamp: int, client_id: int) -> GameStarted:
+    return GameStarted(timestamp, client_id)
+
+
+@event_decoder("player_left_game")
+def decode_player_left_game(
+    msg: pb.PlayerLeftGame, timestamp: int, client_id: int
+) -> PlayerLeft:
+    return PlayerLeft(timestamp, client_id, msg.reason)
+
+
+def decode_event(chunk: pb.ReplayChunk, keep_raw: bool = False) -> ReplayEvent:
+    """Translate a decoded chunk into a `ReplayEvent`, copying out everything
+    needed (so the chunk can safely be reused afterwards). With `keep_raw`,
+    unknown chunks carry their raw content, which is slower."""
+    content = chunk.inner.content
+    content_type = content.WhichOneof("content_type")
+    if content_type is not None:
+        decoder = event_decoders.get(content_type)
+        if decoder is not None:
+            return decoder(
+                getattr(content, content_type), chunk.timestamp, chunk.client_id
+            )
+    raw = unknown_chunk(chunk, chunk.SerializeToString()) if keep_raw else None
+    return UnknownEvent(chunk.timestamp, chunk.client_id, raw)
+
+
+def iter_events(
+    replay: ReplaySource,
+    gzip_offset: Optional[int] = None,
+    recover: bool = False,
+    keep_raw: bool = False,
+) -> Iterable[ReplayEvent]:
+    """Decode a replay into a sequence of `ReplayEvent`s."""
+    chunk = pb.ReplayChunk()
+    for offset, bytestring in split_replay_with_offsets(replay, gzip_offset, recover):
+        yield decode_event(parse_chunk(bytestring, offset, chunk), keep_raw=keep_raw)
+
+
+def peek_map(
+    replay: ReplaySource, gzip_offset: Optional[int] = None, recover: bool = False
+) -> Optional[MapLoaded]:
+    """Find the map a replay was played on from its first few chunks, without
+    simulating the rest of it."""
+    for event in islice(iter_events(replay, gzip_offset, recover), MAP_SCAN_CHUNKS):
+        if isinstance(event, MapLoaded):
+            return event
+    return None
+
+
+@lru_cache(maxsize=None)
+def event_handler_name(event_type: type) -> str:
+    """Name of the GameState method handling an event type, e.g.
+    handle_player_joined for PlayerJoined."""
+    return "handle_" + re.sub(r"(?<!^)(?=[A-Z])", "_", event_type.__name__).lower()
+
+
+ChunkHandler = Callable[["GameState", pb.ReplayChunk], None]
+"""Custom per-chunk logic to run alongside the built-in simulation."""
+
+chunk_handlers: List[ChunkHandler] = []
+"""Chunk handlers that run during every simulation."""
+
+
+def track_command_usage(state: GameState, chunk: pb.ReplayChunk):
+    """Chunk handler that tallies `Client.command_usage`. It isn't run by
+    default as it has to re-encode every command to find its type, so pass it
+    in `handlers` when you need it."""
+    if chunk.inner.content.WhichOneof("content_type") is not None:
+        return
+    if (client := state.clients.get(chunk.client_id)) is None:
+        return
+    tag = unknown_chunk(chunk, chunk.SerializeToString()).tag
+    if tag is None:
+        return
+    client.command_usage[tag] = client.command_usage.get(tag, 0) + 1
+
+
+def register_chunk_handler(handler: ChunkHandler) -> ChunkHandler:
+    """Register a chunk handler to run during every replay simulation.
+
+    Can be used as a decorator. Handlers can stash whatever they collect in
+    their own state, e.g. by being methods of a collector object."""
+    chunk_handlers.append(handler)
+    return handler
+
+
+def content_handler(callback: Callable[..., None], *content_types: str) -> ChunkHandler:
+    """Wrap a callback as a chunk handler that only fires for chunks with the
+    given content types (e.g. "player_left_game").
+
+    The callback is passed the state followed by the content message, and
+    `client_id` and `timestamp` keyword arguments. (To work with decoded
+    events instead, see `iter_events`.)"""
+    wanted = set(content_types)
+
+    def handler(state: GameState, chunk: pb.ReplayChunk):
+        content = chunk.inner.content
+        content_type = content.WhichOneof("content_type")
+        if content_type in wanted:
+            callback(
+                state,
+                getattr(content, content_type),
+                client_id=chunk.client_id,
+                timestamp=chunk.timestamp,
+            )
+
+    return handler
+
+
+SLOT_EVENTS = (SlotChanged, VariableSet)
+"""Events that can only be handled once we know the map's slots"""
+
+LOBBY_EVENTS = (MapLoaded, PlayerJoined, SlotAssigned, *SLOT_EVENTS)
+"""Events that indicate we're still in the lobby"""
+
+
+class ParseMetrics(BaseModel):
+    """Timing and I/O figures for a single simulation of a replay."""
+
+    parse_millis: float = 0.0
+    """Total wall-clock time spent simulating the replay"""
+    decompress_millis: float = 0.0
+    """Portion of parse_millis spent reading and decompressing the stream"""
+    chunks: int = 0
+    """Number of chunks processed"""
+    decompressed_bytes: int = 0
+    """Size of the decompressed message stream (excluding length prefixes)"""
 
 
 class GameState(BaseModel):
     """Stormgate match state machine - reads commands from replay and updates state"""
 
+    build_number: Optional[int] = None
+    compressed_bytes: Optional[int] = None
+    """Size of the replay's gzipped data (i.e. the file minus its header)"""
+    decompressed_bytes: Optional[int] = None
+    """Size of the decompressed message stream, once it's been read to the end
+    - unusually large or small values point to odd customs or truncated files"""
+    recorded_by: Optional[UUID] = None
+    """UUID of the account whose client recorded this replay, if known"""
+    recording_client_id: Optional[int] = None
+    """Client ID of the client that recorded this replay (see `finish`)"""
     map_name: Optional[str] = None
+    map_folder: Optional[str] = None
+    map_seed: Optional[int] = None
+    """Random seed sent with the map. Spawn positions aren't recorded in the
+    lobby messages we can decode, but may well be derived from this."""
     slots: Dict[int, Slot] = {}
     clients: Dict[int, Client] = {}
     slot_assignments: Dict[UUID, int] = {}
+    slot_assignment_nicknames: Dict[UUID, str] = {}
+    """Nicknames sent along with the matchmaking slot assignments"""
+    slot_changes: List[SlotChange] = []
+    faction_changes: List[FactionChange] = []
     game_started: bool = False
     game_started_time: Optional[float] = None
+    game_start_inferred: bool = False
+    """Whether game_started_time was guessed due to a missing StartGame"""
+    lobby_locked_time: Optional[float] = None
+    """Timestamp of the last lobby message before loading began"""
+    early_leave_seconds: float = EARLY_LEAVE_SECONDS
+    """Grace period used for `Client.left_early` (see `mark_early_leavers`)"""
+    metrics: ParseMetrics = Field(default_factory=ParseMetrics, exclude=True)
+    """How long `at_end_of` took to simulate this replay (left out when
+    serializing, since it differs from run to run)"""
+    _deferred_events: List[ReplayEvent] = PrivateAttr(default_factory=list)
+    _first_command_time: Optional[float] = PrivateAttr(default=None)
+    _first_client_id: Optional[int] = PrivateAttr(default=None)
 
     @classmethod
-    def at_end_of(cls, replay: Union[Path, BinaryIO]) -> GameState:
-        """Simulate an entire replay and return the end state."""
-        self = cls()
-        for bytestring in split_replay(replay):
-            chunk = pb.ReplayChunk.FromString(bytestring)
-            self.process(chunk)
-        return self
-
-    def process(self, chunk: pb.ReplayChunk):
-        """Update the state using a single replay chunk/command."""
-        content = chunk.inner.content
-        content_type = content.WhichOneof("content_type")
-        handler = getattr(self, f"handle_{content_type}", None)
-        if handler is not None:
-            handler(
-                getattr(content, content_type),
-                client_id=chunk.client_id,
-                timestamp=chunk.timestamp,
+    def at_end_of(
+        cls,
+        replay: ReplaySource,
+        gzip_offset: Optional[int] = None,
+        handlers: Iterable[ChunkHandler] = (),
+        recover: bool = False,
+        max_chunks: Optional[int] = None,
+        max_seconds: Optional[float] = None,
+        max_bytes: Optional[int] = None,
+    ) -> GameState:
+        """Simulate an entire replay and return the end state.
+
+        Any `handlers` given (along with those registered using
+        `register_chunk_handler`) are called with the state and each chunk
+        after the chunk has been processed. The chunk object is reused, so
+        handlers wanting to keep it around must make a copy.
+
+        If the replay contains more than one game (see `all_games_in`), this
+        is the state at the end of the last one."""
+        return cls.all_games_in(
+            replay, gzip_offset, handlers, recover, max_chunks, max_seconds, max_bytes
+        )[-1]
+
+    @classmethod
+    def all_games_in(
+        cls,
+        replay: ReplaySource,
+        gzip_offset: Optional[int] = None,
+        handlers: Iterable[ChunkHandler] = (),
+        recover: bool = False,
+        max_chunks: Optional[int] = None,
+        max_seconds: Optional[float] = None,
+        max_bytes: Optional[int] = None,
+    ) -> List[GameState]:
+        """Simulate a replay, returning the end state of each game in it.
+
+        Usually there's just one, but when a lobby is reused for a rematch the
+        replay can carry on into a second game, with the same client IDs. We
+        detect this by map details arriving after the game has started.
+
+        For parsing untrusted files, `max_chunks`, `max_seconds` (of wall clock
+        time) and `max_bytes` (of decompressed data) put a limit on the work
+        done: going over any of them raises `SimulationLimitExceeded`, carrying
+        the games simulated so far."""
+        started = time.perf_counter()
+        replay = seekable(replay)
+        build_number = get_build_number(replay, gzip_offset)
+        header = read_header(replay, gzip_offset)
+        recorded_by = uuid_from_path(replay) if isinstance(replay, Path) else None
+        compressed_bytes = replay_size(replay) - header.header_length
+        stream_end = 0
+        # Metrics cover the whole file, so all the games share them:
+        metrics = ParseMetrics()
+
+        def new_game() -> GameState:
+            return cls(
+                build_number=build_number,
+                recorded_by=recorded_by,
+                compressed_bytes=compressed_bytes,
+                metrics=metrics,
+            )
+
+        games = [new_game()]
+        all_handlers = [*chunk_handlers, *handlers]
+        # Long replays have tens of thousands of chunks, so rather than
+        # allocating a new message for each one we parse them all into the
+        # same object. (This means handlers must copy any chunk they want to
+        # hold onto.)
+        chunk = pb.ReplayChunk()
+        stream = iter(
+            split_replay_with_offsets(
+                replay, gzip_offset, recover, max_bytes, max_seconds
             )
+        )
+        while True:
+            before_read = time.perf_counter()
+            try:
+                framed = next(stream, None)
+            except SimulationLimitExceeded as e:
+                games[-1].finish()
+                raise SimulationLimitExceeded(e.limit, e.value, games) from None
+            metrics.decompress_millis += (time.perf_counter() - before_read) * 1000
+            if framed is None:
+                break
+            if max_chunks is not None and metrics.chunks >= max_chunks:
+                games[-1].finish()
+                raise SimulationLimitExceeded("max_chunks", max_chunks, games)
+            offset, bytestring = framed
+            stream_end = offset + len(bytestring)
+            metrics.chunks += 1
+            metrics.decompressed_bytes += len(bytestring)
+            try:
+                parse_chunk(bytestring, offset, chunk)
+            except ChunkDecodeError as e:
+                if not recover:
+                    raise
+                logger.warning(f"Skipping undecodable chunk: {e}")
+                continue
+            event = decode_event(chunk)
+            state = games[-1]
+            if state.is_lobby_reset(event):
+                logger.warning("Lobby was reused for another game, starting afresh")
+                state.finish()
+                state = new_game()
+                games.append(state)
+            state.process(chunk, event, len(bytestring))
+            for handler in all_handlers:
+                handler(state, chunk)
+        games[-1].finish()
+        for game in games:
+            game.decompressed_bytes = stream_end
+        metrics.parse_millis = (time.perf_counter() - started) * 1000
+        return games
+
+    def is_lobby_reset(self, event: ReplayEvent) -> bool:
+        """Whether an event marks the start of a new game in the same replay."""
+        return self.game_started and isinstance(event, MapLoaded)
+
+    @classmethod
+    def state_at(
+        cls,
+        replay: ReplaySource,
+        timestamp: float,
+        gzip_offset: Optional[int] = None,
+        recover: bool = False,
+    ) -> GameState:
+        """Simulate a replay up to and including the given (raw) timestamp,
+        e.g. to see who was still connected at that point. If the lobby was
+        reused, this is the state of whichever game was in progress.
+
+        Per-chunk byte counts (`Client.bytes_sent`) aren't tracked."""
+        replay = seekable(replay)
+        header = read_header(replay, gzip_offset)
+
+        def new_game() -> GameState:
+            return cls(
+                build_number=header.build_number,
+                recorded_by=(
+                    uuid_from_path(replay) if isinstance(replay, Path) else None
+                ),
+            )
+
+        state = new_game()
+        for event in iter_events(replay, gzip_offset, recover):
+            if event.timestamp > timestamp:
+                break
+            if state.is_lobby_reset(event):
+                state = new_game()
+            state.apply(event)
+        state.finish()
+        return state
+
+    def __str__(self):
+        return format_summary(summarize_state(self))
+
+    @property
+    def ticks_per_second(self) -> float:
+        return ticks_per_second(self.build_number)
+
+    @property
+    def recorded_by_observer(self) -> bool:
+        """Whether this replay was recorded by a spectator (e.g. a tournament
+        referee) rather than one of the players."""
+        client = self.clients.get(self.recording_client_id)  # type: ignore
+        return client is not None and client.is_spectator
+
+    @property
+    def teams(self) -> List[Team]:
+        """The teams in this game, in order of their lowest slot.
+
+        Slots without a known team (see `TEAM_VARIABLE_ID`) are grouped as laid
+        out in the match type (see `match_type_info`). A team has lost once all its human players have
+        surrendered; if every team but one has lost, that team won."""
+        teams: Dict[Tuple[str, int], Team] = {}
+        match_type = self.match_type
+        players = self.slot_players()
+        for sp in players:
+            if sp.slot.team is not None:
+                key = ("team", sp.slot.team)
+            elif (layout_team := match_type.team_of_slot(sp.slot_number)) is not None:
+                key = ("layout", layout_team)
+            else:
+                key = ("slot", sp.slot_number)
+            team = teams.setdefault(key, Team(id=len(teams)))
+            team.slots.append(sp.slot_number)
+        for team in teams.values():
+            humans = [
+                sp for sp in players if sp.slot_number in team.slots and not sp.is_ai
+            ]
+            if humans and all(
+                sp.left_game_reason == LeftGameReason.surrender for sp in humans
+            ):
+                team.result = "loss"
+                team.result_confidence = ResultConfidence.explicit
+        undecided = [team for team in teams.values() if team.result is None]
+        if len(teams) > 1 and len(undecided) == 1:
+            undecided[0].result = "win"
+            undecided[0].result_confidence = ResultConfidence.explicit
+        return list(teams.values())
+
+    @property
+    def match_type(self) -> MatchTypeInfo:
+        return match_type_info(self.map_name, self.map_folder)
+
+    @property
+    def compression_ratio(self) -> Optional[float]:
+        """How many times smaller the replay is for being gzipped"""
+        if not self.compressed_bytes or self.decompressed_bytes is None:
+            return None
+        return self.decompressed_bytes / self.compressed_bytes
+
+    @property
+    def is_campaign(self) -> bool:
+        """Whether this is a single-player campaign mission (in which case
+        map_name is the mission name)."""
+        return self.match_type.name == "campaign"
+
+    @property
+    def mod_info(self) -> Optional[ModInfo]:
+        """Details of the mod providing the map, for arcade games."""
+        return mod_info(self.map_folder)
+
+    def team_factions(self) -> List[List[Faction]]:
+        """The factions played on each team."""
+        return [[self.slots[n].faction for n in team.slots] for team in self.teams]
+
+    @property
+    def matchup(self) -> str:
+        """Canonical matchup string, e.g. "VvI" (see `format_matchup`)."""
+        return format_matchup(self.team_factions())
+
+    @property
+    def is_mirror(self) -> bool:
+        """Whether every team has the same faction lineup."""
+        teams = [sorted(team) for team in self.team_factions()]
+        return len(teams) > 1 and all(team == teams[0] for team in teams)
+
+    def game_time(self, timestamp: float) -> Optional[float]:
+        """Convert a chunk timestamp to seconds since the game started."""
+        if self.game_started_time is None:
+            return None
+        return (timestamp - self.game_started_time) / self.ticks_per_second
 
-    def handle_map(self, msg: pb.Map, **__):
-        self.map_name = msg.name
-        slot_count = player_slot_count[msg.name]
-        logger.debug(f"Setting up {slot_count} slots for map {msg.name}")
+    def mark_early_leavers(self, grace_seconds: Optional[float] = None):
+        """Work out `Client.left_early` for every client, optionally with a new
+        grace period. This is done with the default in `finish`, and again by
+        `summarize_state`."""
+        if grace_seconds is not None:
+            self.early_leave_seconds = grace_seconds
+        for client in self.clients.values():
+            left = None
+            if client.left_game_time is not None:
+                left = self.game_time(client.left_game_time)
+            client.left_early = left is not None and left < self.early_leave_seconds
+
+    def slot_players(self) -> List[SlotPlayer]:
+        """List the occupied player slots (by AI or human) in slot order."""
+        result = []
+        for slot_number, slot in sorted(self.slots.items()):
+            if slot.ai_type is not None:
+                result.append(SlotPlayer(slot_number=slot_number, slot=slot))
+            elif slot.client_id is not None:
+                result.append(
+                    SlotPlayer(
+                        slot_number=slot_number,
+                        slot=slot,
+                        client=self.clients[slot.client_id],
+                    )
+                )
+        return result
+
+    def slot_list(self) -> List[NumberedSlot]:
+        """List every slot (occupied or not) in slot order, for when a dict
+        keyed by 1-based slot number is awkward."""
+        return [NumberedSlot(n, slot) for n, slot in sorted(self.slots.items())]
+
+    def slot_for_client(self, client_id: int) -> Optional[NumberedSlot]:
+        """Find the slot a client is sitting in (None for spectators)."""
+        for slot_number, slot in self.slots.items():
+            if slot.client_id == client_id:
+                return NumberedSlot(slot_number, slot)
+        return None
+
+    def client_for_slot(self, slot_number: int) -> Optional[Client]:
+        """Find the client sitting in a slot (None for AI/empty slots)."""
+        slot = self.slots.get(slot_number)
+        if slot is None or slot.client_id is None:
+            return None
+        return self.clients.get(slot.client_id)
+
+    def faction_history(self, slot_player: SlotPlayer) -> List[FactionChange]:
+        """List every faction change made for a player during the lobby.
+
+        Humans are tracked by client, so picks made before they moved slots
+        are included; AIs are tracked by slot."""
+        if slot_player.client is not None:
+            client_id = slot_player.client.client_id
+            return [c for c in self.faction_changes if c.client_id == client_id]
+        return [
+            c
+            for c in self.faction_changes
+            if c.slot_number == slot_player.slot_number and c.client_id is None
+        ]
+
+    def my_slot(self, my_uuids: Sequence[UUID]) -> Optional[SlotPlayer]:
+        """Find the slot occupied by the user owning the given accounts (see
+        `ReplaySummary.perspective` for how ties are broken)."""
+        players = self.slot_players()
+        for uuid in my_uuids:
+            for sp in players:
+                if sp.client is not None and sp.client.uuid == uuid:
+                    return sp
+        return None
+
+    def opponent(self, my_uuids: Sequence[UUID]) -> Optional[SlotPlayer]:
+        """In a two-player game, find the slot of whoever we played against."""
+        players = self.slot_players()
+        me = self.my_slot(my_uuids)
+        if me is None or len(players) != 2:
+            return None
+        return next(sp for sp in players if sp.slot_number != me.slot_number)
+
+    def my_result(self, my_uuids: Sequence[UUID]) -> Optional[str]:
+        """Result of a 1v1 game from our perspective: "win", "loss" or None."""
+        info = summarize_state(self)
+        us, them = info.perspective(my_uuids)
+        if us is None or them is None:
+            return None
+        return infer_result(us, them, info.build_number)
+
+    def process(
+        self,
+        chunk: pb.ReplayChunk,
+        event: Optional[ReplayEvent] = None,
+        size: Optional[int] = None,
+    ):
+        """Update the state using a single replay chunk/command. Pass `event`
+        if the chunk has already been decoded, and `size` if its encoded length
+        is known (e.g. from the stream framing), to save working them out again."""
+        if (client := self.clients.get(chunk.client_id)) is not None:
+            client.bytes_sent += size if size is not None else chunk.ByteSize()
+        self.apply(event if event is not None else decode_event(chunk))
+
+    def apply(self, event: ReplayEvent):
+        """Update the state with a single event - the state at the end of a
+        replay is a fold of this over all its events."""
+        if type(event) is UnknownEvent:
+            # This is the vast majority of events, so it gets a fast path that
+            # skips all the lobby logic and handler lookup below.
+            if (client := self.clients.get(event.client_id)) is not None:
+                client.record_command(self.game_time(event.timestamp))
+                if not self.game_started and self._first_command_time is None:
+                    self._first_command_time = float(event.timestamp)
+            return
+        if isinstance(event, LOBBY_EVENTS):
+            # Anything before the lobby settles down isn't a game command:
+            self._first_command_time = None
+            if not self.game_started:
+                self.lobby_locked_time = float(event.timestamp)
+        if isinstance(event, SLOT_EVENTS) and not self.slots:
+            # Some replays (customs/co-op?) send slot changes before the map
+            # details, so hold onto them until we know what slots exist.
+            logger.debug(f"Deferring {type(event).__name__} until map info is received")
+            self._deferred_events.append(event)
+            return
+        handler = getattr(self, event_handler_name(type(event)), None)
+        if handler is not None:
+            handler(event)
+
+    def handle_map_loaded(self, event: MapLoaded):
+        self.map_name = event.name
+        self.map_folder = event.folder
+        self.map_seed = event.seed
+        slot_count = match_type_info(event.name, event.folder).slot_count
+        logger.debug(f"Setting up {slot_count} slots for map {event.name}")
         for i in range(1, slot_count + 1):
             self.slots[i] = Slot()
+        # Players pre-assigned a slot before the map arrived can sit down now:
+        for client in self.clients.values():
+            if client.slot_number in self.slots:
+                self.seat_client(client.client_id, client.slot_number)  # type: ignore
+        deferred, self._deferred_events = self._deferred_events, []
+        for deferred_event in deferred:
+            self.apply(deferred_event)
 
-    def handle_assign_player_slot(self, msg: pb.AssignPlayerSlot, **__):
-        self.slot_assignments[parse_uuid(msg.uuid)] = msg.slot
-        logger.debug(f"Assigning slot {msg.slot} to {msg.uuid}")
+    def handle_slot_assigned(self, event: SlotAssigned):
+        self.slot_assignments[event.uuid] = event.slot_number
+        if event.nickname:
+            self.slot_assignment_nicknames[event.uuid] = event.nickname
+        logger.debug(f"Assigning slot {event.slot_number} to {event.uuid}")
 
-    def handle_player(self, msg: pb.Player, client_id, **__):
+    def handle_player_joined(self, event: PlayerJoined):
+        client_id = event.client_id
+        placeholder = self.clients.get(client_id)
         self.clients[client_id] = client = Client(
             client_id=client_id,
-            uuid=parse_uuid(msg.uuid),
-            nickname=msg.name.nickname,
-            discriminator=msg.name.discriminator,
+            uuid=event.uuid,
+            nickname=event.nickname,
+            discriminator=event.discriminator,
         )
         logger.debug(f"Setting up player {client_id}: {client.nickname} {client.uuid}")
+        if placeholder is not None:
+            # Either a placeholder made for chunks the client sent before its
+            # Player message, or a second Player message for the same client
+            # (e.g. after a rename). The latest identity wins, but we keep
+            # everything else we know about the client.
+            self.clients[client_id] = client = placeholder.model_copy(
+                update=dict(
+                    uuid=client.uuid,
+                    nickname=client.nickname,
+                    discriminator=client.discriminator,
+                    other_nicknames=list(placeholder.other_nicknames),
+                    is_placeholder=False,
+                )
+            )
+            if not placeholder.is_placeholder:
+                self.note_other_nickname(
+                    client, placeholder.nickname, "earlier Player message"
+                )
+        # The lobby assignment can also disagree with the Player message.
+        # Again we trust the Player message, as it arrives later.
+        if (assigned := self.slot_assignment_nicknames.get(client.uuid)) is not None:
+            self.note_other_nickname(client, assigned, "slot assignment")
+        if self._first_client_id is None:
+            self._first_client_id = client_id
         # If we're in a matchmaking game, the server has pre-assigned a slot for the player:
         if (slot_number := self.slot_assignments.get(client.uuid)) is not None:
-            client.slot_number = slot_number
-            self.slots[slot_number].client_id = client_id
             logger.debug(
                 f"Putting player {client_id} in pre-assigned slot {slot_number}"
             )
+            self.vacate_slot(client)
+            client.slot_number = slot_number
+            if slot_number in self.slots:
+                self.seat_client(client_id, slot_number)
+            # (otherwise the slots aren't set up yet, so handle_map seats them)
+
+    def vacate_slot(self, client: Client):
+        """Empty the slot a client is in (if it's really holding them)."""
+        slot = self.slots.get(client.slot_number)  # type: ignore
+        if slot is not None and slot.client_id == client.client_id:
+            slot.client_id = None
 
-    def handle_player_left_game(self, msg: pb.PlayerLeftGame, client_id, timestamp):
+    def seat_client(self, client_id: int, slot_number: int):
+        """Put a client in a slot, moving anyone else there to the spectators."""
+        slot = self.slots[slot_number]
+        if slot.client_id is not None and slot.client_id != client_id:
+            self.displace_client(slot.client_id, to_slot=None)
+        slot.client_id = client_id
+
+    def note_other_nickname(self, client: Client, nickname: str, source: str):
+        """Record a conflicting nickname for a client, if it is one."""
+        if nickname == client.nickname or nickname in client.other_nicknames:
+            return
+        logger.warning(
+            f"Nickname {nickname!r} from {source} doesn't match {client.nickname!r}"
+            f" for client {client.client_id}; using the latter"
+        )
+        client.other_nicknames.append(nickname)
+
+    def handle_player_left(self, event: PlayerLeft):
+        client_id, timestamp = event.client_id, event.timestamp
         if self.game_started:
-            self.clients[client_id].left_game_time = timestamp
-            self.clients[client_id].left_game_reason = LeftGameReason(msg.reason)
+            client = self.get_client(client_id)
+            client.left_game_time = timestamp
+            client.left_game_reason = (
+                LeftGameReason.from_value(event.reason) or LeftGameReason.unknown
+            )
         else:
             client = self.clients.pop(client_id, None)
             # In aborted ladder games, we sometimes get a left game before the player joined message
@@ -278,14 +2386,13 @@ class GameState(BaseModel):
                 if slot.client_id == client_id:
                     slot.client_id = None
 
-    def handle_change_slot(self, msg: pb.LobbyChangeSlot, client_id, **__):
-        if not self.slots:
-            raise ReplayParsingError("Received slot change before map info?")
-        client = self.clients[client_id]
-        if client.slot_number is not None and client.slot_number != 255:
-            self.slots[client.slot_number].client_id = None
-        if msg.choice.WhichOneof("choice_type") == "specific_slot":
-            slot_number = msg.choice.specific_slot.slot
+    def handle_slot_changed(self, event: SlotChanged):
+        client_id, timestamp = event.client_id, event.timestamp
+        client = self.get_client(client_id)
+        from_slot = client.slot_number
+        self.vacate_slot(client)
+        if event.slot_number is not None:
+            slot_number = event.slot_number
         else:
             # Client did not choose a specific slot, so they get the first open human slot
             for slot_number, slot in self.slots.items():
@@ -293,42 +2400,219 @@ class GameState(BaseModel):
                     break
             else:
                 # No open slots, become spectator
-                slot_number = 255
+                slot_number = SPECTATOR_SLOT
         client.slot_number = slot_number
-        if slot_number != 255:
+        change = SlotChange(
+            timestamp=timestamp,
+            client_id=client_id,
+            from_slot=from_slot,
+            to_slot=slot_number,
+        )
+        self.slot_changes.append(change)
+        if slot_number != SPECTATOR_SLOT:
             slot = self.slots[slot_number]
             if slot.type != SlotType.human:
                 raise ReplayParsingError("Client assigned to non-human slot?")
             if slot.client_id is not None:
-                raise ReplayParsingError("Client assigned to already occupied slot?")
+                change.displaced_client_id = slot.client_id
+                self.displace_client(slot.client_id, to_slot=from_slot)
             slot.client_id = client_id
             logger.debug(f"Putting player {client_id} in slot {slot_number}")
 
-    def handle_set_variable(self, msg: pb.LobbySetVariable, **__):
-        slot = self.slots[msg.slot]
-        key = msg.variable_id
-        value = msg.value
-        if key == 374945738:
-            slot.type = SlotType(value)
-            logger.debug(f"Set slot[{msg.slot}].type = {slot.type}")
+    def get_client(self, client_id: int) -> Client:
+        """Look up a client, creating a placeholder (with a warning) if we
+        haven't seen a Player message for it.
+
+        Late joiners and engine-internal clients (e.g. in co-op) can send
+        messages without ever introducing themselves, and that shouldn't
+        prevent us from parsing the rest of the replay."""
+        if (client := self.clients.get(client_id)) is None:
+            logger.warning(f"Unknown client {client_id}, creating a placeholder")
+            client = self.clients[client_id] = Client(
+                client_id=client_id,
+                uuid=UUID(int=0),
+                nickname=f"Client {client_id}",
+                discriminator="",
+                is_placeholder=True,
+            )
+        return client
+
+    def displace_client(self, client_id: int, to_slot: Optional[int]):
+        """Move a client out of the way of someone else taking their slot.
+
+        We've seen replays where a client moves into an occupied slot; we
+        assume this is a swap, so the displaced client takes the mover's old
+        slot if it's free, and becomes a spectator otherwise."""
+        displaced = self.clients[client_id]
+        if (
+            to_slot is None
+            or to_slot not in self.slots
+            or self.slots[to_slot].client_id is not None
+        ):
+            to_slot = SPECTATOR_SLOT
+        else:
+            self.slots[to_slot].client_id = client_id
+        logger.warning(
+            f"Client {client_id} displaced from occupied slot "
+            f"{displaced.slot_number} to slot {to_slot}"
+        )
+        displaced.slot_number = to_slot
+
+    def handle_variable_set(self, event: VariableSet):
+        slot_number = event.slot_number
+        slot = self.slots[slot_number]
+        key = event.variable_id
+        value = event.value
+        variable = lobby_variable(self.map_name, key)
+        if variable == LobbyVariable.slot_type:
+            if (slot_type := SlotType.from_value(value)) is None:
+                logger.warning(f"Ignoring unknown slot type {value} for slot {slot_number}")
+                return
+            slot.type = slot_type
+            logger.debug(f"Set slot[{slot_number}].type = {slot.type}")
             if slot.type == SlotType.ai:
                 slot.ai_type = AIType(0)
-                logger.debug(f"Set slot[{msg.slot}].ai_type = {slot.ai_type}")
+                slot.ai_type_value = 0
+                logger.debug(f"Set slot[{slot_number}].ai_type = {slot.ai_type}")
             else:
                 slot.ai_type = None
-                logger.debug(f"Set slot[{msg.slot}].ai_type = None")
-        elif key == 2952722564:
-            slot.faction = Faction(value)
-            logger.debug(f"Set slot[{msg.slot}].faction = {slot.faction}")
-        elif key == 655515685:
+                slot.ai_type_value = None
+                logger.debug(f"Set slot[{slot_number}].ai_type = None")
+        elif variable == LobbyVariable.faction:
+            if (faction := Faction.from_value(value)) is None:
+                logger.warning(f"Ignoring unknown faction {value} for slot {slot_number}")
+                return
+            slot.faction = faction
+            self.faction_changes.append(
+                FactionChange(
+                    timestamp=event.timestamp,
+                    slot_number=slot_number,
+                    client_id=slot.client_id,
+                    faction=faction,
+                )
+            )
+            logger.debug(f"Set slot[{slot_number}].faction = {slot.faction}")
+        elif variable == LobbyVariable.ai_type:
             slot.ai_type = AIType(value)
-            logger.debug(f"Set slot[{msg.slot}].ai_type = {slot.ai_type}")
+            slot.ai_type_value = value
+            logger.debug(f"Set slot[{slot_number}].ai_type = {slot.ai_type}")
+        elif variable == LobbyVariable.team:
+            slot.team_value = value
+            logger.debug(f"Set slot[{slot_number}].team = {value}")
+        elif variable == LobbyVariable.color:
+            slot.color = value
+            logger.debug(f"Set slot[{slot_number}].color = {value}")
+        else:
+            slot.variables[key] = value
+            logger.debug(f"Set slot[{slot_number}].variables[{key}] = {value}")
 
-    def handle_start_game(self, _: pb.StartGame, timestamp, **__):
+    def handle_game_started(self, event: GameStarted):
+        client_id, timestamp = event.client_id, event.timestamp
+        # Each client sends its own StartGame once loaded, so the game really
+        # starts when the last one arrives; but some customs send repeats,
+        # which we ignore.
+        if (client := self.clients.get(client_id)) is not None:
+            if client.start_game_time is not None:
+                logger.debug(f"Ignoring repeated StartGame from client {client_id}")
+                return
+            client.start_game_time = float(timestamp)
         logger.debug("Marking game as started")
         self.game_started = True
-        self.game_started_time = float(timestamp)
+        self.game_started_time = max(self.game_started_time or 0.0, float(timestamp))
+
+    def finish(self):
+        """Tidy up after the last chunk has been processed.
+
+        If we never saw a StartGame, we fall back to treating the first command
+        sent after the lobby settled as the start of the game."""
+        if self.game_started_time is None and self._first_command_time is not None:
+            logger.debug("No StartGame received, inferring start from first command")
+            self.game_started_time = self._first_command_time
+            self.game_start_inferred = True
+
+        self.mark_early_leavers()
+
+        if self.lobby_locked_time is not None:
+            for client in self.clients.values():
+                if client.start_game_time is not None:
+                    client.load_time_seconds = (
+                        client.start_game_time - self.lobby_locked_time
+                    ) / self.ticks_per_second
+
+        # If we know which account recorded the replay (from the folder it's
+        # stored in), find its client; otherwise guess it's the first client
+        # to join, since a replay starts recording when the local client
+        # enters the lobby.
+        if self.recording_client_id is None:
+            if self.recorded_by is not None:
+                for client_id, client in self.clients.items():
+                    if client.uuid == self.recorded_by:
+                        self.recording_client_id = client_id
+                        break
+                else:
+                    # Rather than guess and end up naming two different
+                    # recorders, leave the client unknown:
+                    logger.warning(
+                        f"Replay was recorded by {self.recorded_by}, "
+                        "but no client in it has that UUID"
+                    )
+            elif self._first_client_id in self.clients:
+                self.recording_client_id = self._first_client_id
+                self.recorded_by = self.clients[self._first_client_id].uuid
 
 
 class ReplayParsingError(Exception):
     pass
+
+
+class NotAReplayError(ReplayParsingError):
+    """The file isn't a Stormgate replay at all (rather than being a replay we
+    failed to understand)."""
+
+    def __init__(self, message: str, file_type: Optional[str] = None):
+        if file_type is not None:
+            message = f"Not a Stormgate replay - looks like {file_type}. {message}"
+        else:
+            message = f"Not a Stormgate replay. {message}"
+        super().__init__(message)
+        self.file_type = file_type
+        """What the file appears to be instead (e.g. "a zip archive"), if known"""
+
+
+class SimulationLimitExceeded(ReplayParsingError):
+    """A replay simulation was abandoned for going over one of its limits
+    (see `GameState.all_games_in`)."""
+
+    def __init__(self, limit: str, value: float, games: Sequence[GameState] = ()):
+        self.limit = limit
+        """Name of the limit that was exceeded (max_chunks, max_seconds or
+        max_bytes)"""
+        self.value = value
+        self.games = list(games)
+        """State of each game as of when the simulation was stopped (empty if
+        it was stopped while reading the replay, e.g. by `split_replay`)"""
+        if games:
+            chunks = games[-1].metrics.chunks
+            message = f"Gave up simulating replay after {chunks} chunks"
+        else:
+            message = "Gave up reading replay"
+        super().__init__(f"{message}: exceeded {limit}={value}")
+
+    @property
+    def state(self) -> GameState:
+        """The partial state of the game being simulated when we stopped"""
+        return self.games[-1]
+
+
+class ChunkDecodeError(ReplayParsingError):
+    """A single chunk of the replay couldn't be decoded as protobuf."""
+
+    def __init__(self, offset: int, data: bytes, reason: str):
+        self.offset = offset
+        """Offset of the chunk within the decompressed replay stream"""
+        self.data = data
+        self.reason = reason
+        super().__init__(
+            f"Failed to decode {len(data)}-byte chunk at offset {offset} "
+            f"(0x{offset:x}) of the decompressed stream: {reason}\n{hexdump(data)}"
+        )
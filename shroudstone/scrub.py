@@ -0,0 +1,87 @@
+"""Strip identifying details of other players from parsed replay data.
+
+This operates on the data we export (GameState and ReplaySummary), never on
+the replay itself, so that e.g. match datasets can be published without
+revealing who the opponents were. Other players' UUIDs are replaced with
+salted hashes (so the same player is still recognisable across games in one
+dataset) and their discriminators are removed. The salt must be kept secret:
+UUIDs are visible to anyone who has played against you, so hashes made with a
+known salt can be reversed just by hashing candidates."""
+from __future__ import annotations
+import hashlib
+import secrets
+from typing import Optional, Sequence, TypeVar
+from uuid import UUID
+
+from shroudstone.replay import GameState, Player, ReplaySummary, Spectator
+
+M = TypeVar("M", Player, Spectator)
+
+
+def new_salt() -> str:
+    """A random salt, for when the caller doesn't need stable hashes across
+    separate runs."""
+    return secrets.token_hex(16)
+
+
+def hash_uuid(uuid: UUID, salt: str) -> UUID:
+    """A stable pseudonymous stand-in for a UUID. Without knowing the salt,
+    it isn't feasible to tell which UUID it came from."""
+    if not salt:
+        raise ValueError("A secret salt is required to hash UUIDs")
+    digest = hashlib.sha256(f"{salt}:{uuid}".encode()).digest()
+    return UUID(bytes=digest[:16], version=4)
+
+
+def _scrub_uuid(
+    uuid: Optional[UUID], keep_me: Sequence[UUID], salt: str
+) -> Optional[UUID]:
+    if uuid is None or uuid in keep_me:
+        return uuid
+    return hash_uuid(uuid, salt)
+
+
+def scrub(state: GameState, keep_me: Sequence[UUID], salt: str) -> GameState:
+    """A copy of `state` with the UUIDs and discriminators of every account
+    not in `keep_me` hashed/removed. The original is left untouched.
+
+    The recording player (`GameState.recorded_by`) is always kept, as the
+    person sharing the replay is presumably happy to be identified."""
+    if state.recorded_by is not None:
+        keep_me = [*keep_me, state.recorded_by]
+    state = state.model_copy(deep=True)
+    for client in state.clients.values():
+        if client.uuid in keep_me:
+            continue
+        client.uuid = hash_uuid(client.uuid, salt)
+        client.discriminator = ""
+    state.recorded_by = _scrub_uuid(state.recorded_by, keep_me, salt)
+    state.slot_assignments = {
+        _scrub_uuid(uuid, keep_me, salt): slot  # type: ignore
+        for uuid, slot in state.slot_assignments.items()
+    }
+    state.slot_assignment_nicknames = {
+        _scrub_uuid(uuid, keep_me, salt): nickname  # type: ignore
+        for uuid, nickname in state.slot_assignment_nicknames.items()
+    }
+    return state
+
+
+def _scrub_person(person: M, keep_me: Sequence[UUID], salt: str) -> M:
+    if person.uuid is None or person.uuid not in keep_me:
+        person.uuid = _scrub_uuid(person.uuid, keep_me, salt)
+        person.nickname_discriminator = None
+    return person
+
+
+def scrub_summary(
+    info: ReplaySummary, keep_me: Sequence[UUID], salt: str
+) -> ReplaySummary:
+    """As `scrub`, but for a replay summary. Summaries don't record who made
+    the replay, so pass the recording player in `keep_me` if they should be
+    kept."""
+    info = info.model_copy(deep=True)
+    info.players = [_scrub_person(p, keep_me, salt) for p in info.players]
+    info.spectators = [_scrub_person(s, keep_me, salt) for s in info.spectators]
+    return info
+
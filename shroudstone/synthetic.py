@@ -0,0 +1,430 @@
+"""Synthetic replay data for exercising the parser.
+
+`ReplayBuilder` writes scripted scenarios out as real .SGReplay files. This
+module can also generate random (but plausible) sequences of lobby and game
+chunks, and check that simulating them leaves the GameState consistent. It's
+a cheap way to shake out the ordering bugs that turn up in unusual replays
+(co-op lobbies, late joiners and so on)."""
+from __future__ import annotations
+import gzip
+import io
+import logging
+from pathlib import Path
+import random
+import struct
+import traceback
+from typing import Dict, List, NamedTuple, Optional, Set
+from uuid import UUID
+
+from shroudstone import stormgate_pb2 as pb
+from shroudstone.replay import (
+    FRIGATE,
+    SPECTATOR_SLOT,
+    AIType,
+    Faction,
+    GameState,
+    LeftGameReason,
+    ReplayParsingError,
+    SlotType,
+    match_type_info,
+    summarize_state,
+    ticks_per_second,
+    uuid_parts,
+    write_varint,
+)
+
+logger = logging.getLogger(__name__)
+
+SERVER_CLIENT_ID = 64
+"""Client ID used by the matchmaking server for AssignPlayerSlot messages"""
+
+SLOT_TYPE_VARIABLE = 374945738
+FACTION_VARIABLE = 2952722564
+AI_TYPE_VARIABLE = 655515685
+
+PLAYABLE_FACTIONS = [Faction.vanguard, Faction.infernals, Faction.catgirls]
+
+
+def _chunk(timestamp: int, client_id: int) -> pb.ReplayChunk:
+    return pb.ReplayChunk(timestamp=timestamp, client_id=client_id)
+
+
+def _set_uuid(msg: pb.UUID, uuid: UUID):
+    msg.part1, msg.part2 = uuid_parts(uuid)
+
+
+def map_chunk(name: str, timestamp: int = 0, seed: int = 0) -> pb.ReplayChunk:
+    chunk = _chunk(timestamp, SERVER_CLIENT_ID)
+    chunk.inner.content.map.name = name
+    chunk.inner.content.map.seed = seed
+    return chunk
+
+
+def player_chunk(
+    timestamp: int, client_id: int, uuid: UUID, nickname: str, discriminator: str
+) -> pb.ReplayChunk:
+    chunk = _chunk(timestamp, client_id)
+    player = chunk.inner.content.player
+    _set_uuid(player.uuid, uuid)
+    player.name.nickname = nickname
+    player.name.discriminator = discriminator
+    return chunk
+
+
+def change_slot_chunk(
+    timestamp: int, client_id: int, slot: Optional[int] = None
+) -> pb.ReplayChunk:
+    """A request to move to a specific slot, or the next free one if None."""
+    chunk = _chunk(timestamp, client_id)
+    change_slot = chunk.inner.content.change_slot
+    if slot is None:
+        change_slot.choice.SetInParent()
+    else:
+        change_slot.choice.specific_slot.slot = slot
+    return chunk
+
+
+def set_variable_chunk(
+    timestamp: int, client_id: int, slot: int, variable_id: int, value: int
+) -> pb.ReplayChunk:
+    chunk = _chunk(timestamp, client_id)
+    set_variable = chunk.inner.content.set_variable
+    set_variable.slot = slot
+    set_variable.variable_id = variable_id
+    set_variable.value = value
+    return chunk
+
+
+def start_game_chunk(timestamp: int, client_id: int) -> pb.ReplayChunk:
+    chunk = _chunk(timestamp, client_id)
+    chunk.inner.content.start_game.SetInParent()
+    return chunk
+
+
+def left_game_chunk(
+    timestamp: int, client_id: int, uuid: UUID, reason: int
+) -> pb.ReplayChunk:
+    chunk = _chunk(timestamp, client_id)
+    left = chunk.inner.content.player_left_game
+    _set_uuid(left.player_uuid, uuid)
+    left.reason = int(reason)  # type: ignore
+    return chunk
+
+
+def assign_slot_chunk(uuid: UUID, slot: int, nickname: str = "") -> pb.ReplayChunk:
+    chunk = _chunk(0, SERVER_CLIENT_ID)
+    assign = chunk.inner.content.assign_player_slot
+    _set_uuid(assign.uuid, uuid)
+    assign.slot = slot
+    assign.nickname = nickname
+    return chunk
+
+
+def command_chunk(timestamp: int, client_id: int) -> pb.ReplayChunk:
+    """A chunk with no content we understand, standing in for a game command."""
+    return _chunk(timestamp, client_id)
+
+
+def random_chunks(rng: random.Random) -> List[pb.ReplayChunk]:
+    """Generate a plausible-ish replay: some clients join a lobby, shuffle
+    between slots and pick factions, then load in, play and leave - with the
+    order of events jumbled a little, as real replays sometimes are."""
+    map_name = rng.choice(["Broken Crown", "WreckHavoc", "Boulder Bay"])
+    slot_count = match_type_info(map_name).slot_count
+    n_clients = rng.randint(1, slot_count + 1)
+    uuids = [UUID(int=rng.getrandbits(128)) for _ in range(n_clients)]
+    client_ids = rng.sample(range(1, 32), n_clients)
+    # Matchmaking only ever fills the player slots:
+    ladder = n_clients <= slot_count and rng.random() < 0.5
+
+    lobby: List[pb.ReplayChunk] = []
+    if ladder:
+        for i, uuid in enumerate(uuids):
+            lobby.append(assign_slot_chunk(uuid, i + 1, nickname=f"Player{i}"))
+    for i, (client_id, uuid) in enumerate(zip(client_ids, uuids)):
+        lobby.append(player_chunk(0, client_id, uuid, f"Player{i}", f"{i:04d}"))
+        for _ in range(rng.randint(0, 3)):
+            slot = rng.choice([None, rng.randint(1, slot_count), SPECTATOR_SLOT])
+            lobby.append(change_slot_chunk(0, client_id, slot))
+        slot = rng.randint(1, slot_count)
+        if rng.random() < 0.8:
+            faction = int(rng.choice(PLAYABLE_FACTIONS))
+            lobby.append(
+                set_variable_chunk(0, client_id, slot, FACTION_VARIABLE, faction)
+            )
+        if rng.random() < 0.2:
+            ai = int(SlotType.ai)
+            lobby.append(set_variable_chunk(0, client_id, slot, SLOT_TYPE_VARIABLE, ai))
+            ai_type = rng.randint(0, 3)
+            lobby.append(
+                set_variable_chunk(0, client_id, slot, AI_TYPE_VARIABLE, ai_type)
+            )
+    # Co-op replays have been seen with the map arriving after the lobby chatter:
+    map_position = rng.randint(0, len(lobby)) if rng.random() < 0.3 else 0
+    lobby.insert(map_position, map_chunk(map_name))
+
+    chunks = []
+    timestamp = 0
+    for chunk in lobby:
+        timestamp += rng.randint(0, 50)
+        chunk.timestamp = timestamp
+        chunks.append(chunk)
+    for client_id in client_ids:
+        if rng.random() < 0.9:
+            timestamp += rng.randint(0, 500)
+            chunks.append(start_game_chunk(timestamp, client_id))
+    for _ in range(rng.randint(0, 200)):
+        timestamp += rng.randint(0, 100)
+        chunks.append(command_chunk(timestamp, rng.choice(client_ids)))
+    for client_id, uuid in zip(client_ids, uuids):
+        if rng.random() < 0.9:
+            timestamp += rng.randint(0, 1000)
+            # (Including one value past the known reasons, as proto3 enums
+            # are open and the game may add more)
+            reason = rng.randint(0, len(LeftGameReason))
+            chunks.append(left_game_chunk(timestamp, client_id, uuid, reason))
+    return chunks
+
+
+def check_invariants(state: GameState) -> List[str]:
+    """List any ways in which a simulated GameState is inconsistent."""
+    problems = []
+    seated = {}
+    for slot_number, slot in state.slots.items():
+        if slot.client_id is None:
+            continue
+        if slot.client_id in seated:
+            problems.append(
+                f"Client {slot.client_id} is in slots "
+                f"{seated[slot.client_id]} and {slot_number}"
+            )
+        seated[slot.client_id] = slot_number
+        client = state.clients.get(slot.client_id)
+        if client is None:
+            problems.append(
+                f"Slot {slot_number} holds unknown client {slot.client_id}"
+            )
+        elif client.slot_number != slot_number:
+            problems.append(
+                f"Slot {slot_number} holds client {slot.client_id}, "
+                f"but the client thinks it's in slot {client.slot_number}"
+            )
+    for client_id, client in state.clients.items():
+        seat = client.seat
+        if seat.kind == "slot" and seated.get(client_id) != seat.slot_number:
+            problems.append(
+                f"Client {client_id} thinks it's in slot {seat.slot_number}, "
+                "but the slot doesn't hold it"
+            )
+    if state.game_started and state.game_started_time is None:
+        problems.append("Game started but has no start time")
+    try:
+        summarize_state(state)
+    except ReplayParsingError:
+        # A controlled failure on a nonsensical replay is fine.
+        pass
+    except Exception:
+        problems.append(f"summarize_state crashed:\n{traceback.format_exc()}")
+    return problems
+
+
+class FuzzFailure(NamedTuple):
+    seed: int
+    """Seed that reproduces the failure with `random_chunks`"""
+    problems: List[str]
+
+
+def fuzz_simulator(iterations: int = 1000, seed: int = 0) -> List[FuzzFailure]:
+    """Simulate `iterations` random replays, returning any that crashed the
+    simulator or left it in an inconsistent state."""
+    failures = []
+    for i in range(seed, seed + iterations):
+        chunks = random_chunks(random.Random(i))
+        state = GameState()
+        try:
+            for chunk in chunks:
+                state.process(chunk)
+            state.finish()
+        except ReplayParsingError:
+            continue
+        except Exception:
+            problem = f"Simulation crashed:\n{traceback.format_exc()}"
+            failures.append(FuzzFailure(i, [problem]))
+            continue
+        if problems := check_invariants(state):
+            failures.append(FuzzFailure(i, problems))
+    return failures
+
+
+class ReplayBuilder:
+    """Script a match and write it out as a valid .SGReplay file, for use as
+    a test fixture or demo without any real player data in it.
+
+    Players and AIs are added to the lobby, then `start` is called and game
+    events are added at times given in seconds since the start:
+
+        builder = ReplayBuilder(map_name="Broken Crown")
+        me = builder.add_player("Alice", Faction.vanguard)
+        them = builder.add_player("Bob", Faction.infernals)
+        builder.start()
+        builder.command(me, 10)
+        builder.surrender(them, 300)
+        builder.leave(me, 305)
+        builder.write(Path("fixture.SGReplay"))"""
+
+    def __init__(
+        self,
+        map_name: str = "Broken Crown",
+        build_number: int = FRIGATE,
+        ladder: bool = True,
+    ):
+        self.map_name = map_name
+        self.build_number = build_number
+        self.ladder = ladder
+        """Whether players are placed by the matchmaking server (AssignPlayerSlot)
+        or choose their own slots as in a custom game"""
+        self._chunks: List[pb.ReplayChunk] = [map_chunk(map_name)]
+        self._uuids: Dict[int, UUID] = {}
+        self._taken_slots: Set[int] = set()
+        self._next_client_id = 1
+        self._lobby_tick = 0
+        self._start_tick: Optional[int] = None
+
+    def _lobby_timestamp(self) -> int:
+        if self._start_tick is not None:
+            raise ValueError("Lobby events must be added before start()")
+        self._lobby_tick += 1
+        return self._lobby_tick
+
+    def _game_timestamp(self, seconds: float) -> int:
+        if self._start_tick is None:
+            raise ValueError("Call start() before adding game events")
+        return self._start_tick + round(seconds * ticks_per_second(self.build_number))
+
+    def _take_slot(self, slot: Optional[int]) -> int:
+        slot_count = match_type_info(self.map_name).slot_count
+        if slot is None:
+            free = [s for s in range(1, slot_count + 1) if s not in self._taken_slots]
+            if not free:
+                raise ValueError(f"No free slots left on {self.map_name}")
+            slot = free[0]
+        elif slot in self._taken_slots or not 1 <= slot <= slot_count:
+            raise ValueError(f"Slot {slot} isn't available on {self.map_name}")
+        self._taken_slots.add(slot)
+        return slot
+
+    def add_player(
+        self,
+        nickname: str,
+        faction: Faction = Faction.vanguard,
+        slot: Optional[int] = None,
+        uuid: Optional[UUID] = None,
+        discriminator: str = "0000",
+    ) -> int:
+        """Add a human player to the lobby, returning their client ID."""
+        slot = self._take_slot(slot)
+        if uuid is None:
+            uuid = UUID(int=self._next_client_id)
+        client_id = self._next_client_id
+        self._next_client_id += 1
+        self._uuids[client_id] = uuid
+        if self.ladder:
+            chunk = assign_slot_chunk(uuid, slot, nickname)
+            chunk.timestamp = self._lobby_timestamp()
+            self._chunks.append(chunk)
+        self._chunks.append(
+            player_chunk(
+                self._lobby_timestamp(), client_id, uuid, nickname, discriminator
+            )
+        )
+        if not self.ladder:
+            timestamp = self._lobby_timestamp()
+            self._chunks.append(change_slot_chunk(timestamp, client_id, slot))
+        timestamp = self._lobby_timestamp()
+        self._chunks.append(
+            set_variable_chunk(
+                timestamp, client_id, slot, FACTION_VARIABLE, int(faction)
+            )
+        )
+        return client_id
+
+    def add_spectator(self, nickname: str, uuid: Optional[UUID] = None) -> int:
+        """Add a client who watches from the spectator slot."""
+        if uuid is None:
+            uuid = UUID(int=self._next_client_id)
+        client_id = self._next_client_id
+        self._next_client_id += 1
+        self._uuids[client_id] = uuid
+        self._chunks.append(
+            player_chunk(self._lobby_timestamp(), client_id, uuid, nickname, "0000")
+        )
+        self._chunks.append(
+            change_slot_chunk(self._lobby_timestamp(), client_id, SPECTATOR_SLOT)
+        )
+        return client_id
+
+    def add_ai(
+        self,
+        ai_type: AIType = AIType.MurderBotJr,
+        faction: Faction = Faction.vanguard,
+        slot: Optional[int] = None,
+    ):
+        """Put an AI in a slot. The first human player acts as lobby host."""
+        if not self._uuids:
+            raise ValueError("Add a human player (the lobby host) before any AIs")
+        slot = self._take_slot(slot)
+        host = min(self._uuids)
+        for variable_id, value in [
+            (SLOT_TYPE_VARIABLE, int(SlotType.ai)),
+            (AI_TYPE_VARIABLE, int(ai_type)),
+            (FACTION_VARIABLE, int(faction)),
+        ]:
+            timestamp = self._lobby_timestamp()
+            self._chunks.append(
+                set_variable_chunk(timestamp, host, slot, variable_id, value)
+            )
+
+    def start(self):
+        """End the lobby; every client sends StartGame as it finishes loading."""
+        self._start_tick = self._lobby_tick + 1
+        for client_id in self._uuids:
+            self._chunks.append(start_game_chunk(self._start_tick, client_id))
+
+    def command(self, client_id: int, seconds: float):
+        """Add an (opaque) game command from a client."""
+        self._chunks.append(command_chunk(self._game_timestamp(seconds), client_id))
+
+    def leave(
+        self,
+        client_id: int,
+        seconds: float,
+        reason: LeftGameReason = LeftGameReason.leave,
+    ):
+        self._chunks.append(
+            left_game_chunk(
+                self._game_timestamp(seconds), client_id, self._uuids[client_id], reason
+            )
+        )
+
+    def surrender(self, client_id: int, seconds: float):
+        self.leave(client_id, seconds, LeftGameReason.surrender)
+
+    def chunks(self) -> List[pb.ReplayChunk]:
+        """The scripted chunks, in timestamp order."""
+        return sorted(self._chunks, key=lambda c: c.timestamp)
+
+    def to_bytes(self) -> bytes:
+        """Encode the scenario as the contents of a .SGReplay file."""
+        out = io.BytesIO()
+        out.write(bytes(12))
+        out.write(struct.pack("<i", self.build_number))
+        # Fixed mtime, so regenerated fixtures are byte-for-byte identical
+        with gzip.GzipFile(fileobj=out, mode="wb", mtime=0) as gz:
+            for chunk in self.chunks():
+                bytestring = chunk.SerializeToString()
+                write_varint(gz, len(bytestring))
+                gz.write(bytestring)
+        return out.getvalue()
+
+    def write(self, path: Path):
+        path.write_bytes(self.to_bytes())
@@ -0,0 +1,94 @@
+"""Long-running replay renamer, for running in the background at login.
+
+This does the same job as the GUI's "Automatically rename new replays" toggle
+without needing a window open. It stops cleanly on Ctrl-C/SIGTERM (finishing
+any rename in progress first), and keeps a small state file describing what
+it's been up to, so you can check on it from elsewhere."""
+from __future__ import annotations
+from datetime import datetime, timezone
+import logging
+import os
+from pathlib import Path
+import signal
+import threading
+from typing import Callable, Dict, Optional
+
+from pydantic import BaseModel
+
+from shroudstone.config import data_dir
+
+logger = logging.getLogger(__name__)
+
+WATCHER_STATE_FILE = data_dir / "watcher-state.json"
+
+DEFAULT_INTERVAL_SECONDS = 30
+"""How often to look for new replays (the same as the GUI)"""
+
+
+class WatcherState(BaseModel):
+    pid: int
+    started: datetime
+    stopped: Optional[datetime] = None
+    """Set when the watcher shuts down cleanly"""
+    last_run: Optional[datetime] = None
+    runs: int = 0
+    renamed: int = 0
+    errors: int = 0
+
+    def save(self, path: Path):
+        tmp = path.with_name(f"{path.name}.tmp")
+        tmp.write_text(self.model_dump_json(indent=2), encoding="utf-8")
+        tmp.replace(path)
+
+    @staticmethod
+    def load(path: Path = WATCHER_STATE_FILE) -> Optional[WatcherState]:
+        if not path.exists():
+            return None
+        return WatcherState.model_validate_json(path.read_text(encoding="utf-8"))
+
+
+def watch(
+    rename: Callable[[bool], Optional[Dict[str, int]]],
+    interval: float = DEFAULT_INTERVAL_SECONDS,
+    state_file: Path = WATCHER_STATE_FILE,
+    stop: Optional[threading.Event] = None,
+) -> WatcherState:
+    """Call `rename` every `interval` seconds until stopped, by a signal or by
+    setting `stop`. `rename` is passed True on its first call only (e.g. so
+    the replays are backed up once at startup rather than every time), and
+    should return counts of what it did like `renamer.rename_replays`."""
+    if stop is None:
+        stop = threading.Event()
+
+    def request_stop(signum, _):
+        logger.info(f"Received signal {signum}, stopping after the current run.")
+        stop.set()  # type: ignore
+
+    previous_handlers = {}
+    if threading.current_thread() is threading.main_thread():
+        for signum in (signal.SIGINT, signal.SIGTERM):
+            previous_handlers[signum] = signal.signal(signum, request_stop)
+
+    state = WatcherState(pid=os.getpid(), started=datetime.now(timezone.utc))
+    state.save(state_file)
+    logger.info(f"Watching for new replays every {interval:g} seconds.")
+    try:
+        while not stop.is_set():
+            try:
+                counts = rename(state.runs == 0) or {}
+            except Exception:
+                logger.exception("Unexpected error while renaming replays")
+                counts = {"error": 1}
+            state.runs += 1
+            state.renamed += counts.get("renamed", 0)
+            state.errors += counts.get("error", 0)
+            state.last_run = datetime.now(timezone.utc)
+            state.save(state_file)
+            stop.wait(interval)
+    finally:
+        for signum, handler in previous_handlers.items():
+            signal.signal(signum, handler)
+        state.stopped = datetime.now(timezone.utc)
+        state.save(state_file)
+        logger.info("Stopped watching for new replays.")
+    return state
@@ -0,0 +1,93 @@
+"""Write the synthetic replays in tests/corpus and their golden files.
+
+The replays are scripted with `ReplayBuilder`, so they contain no real player
+data. Run this after adding a scenario or changing the builder, then commit
+the results:
+
+    python tests/make_corpus.py
+
+Golden files for existing replays are left alone; to accept a parser change,
+run `shroudstone check-corpus tests/corpus --update` instead."""
+from pathlib import Path
+from typing import Callable, Dict
+from uuid import UUID
+
+from shroudstone.corpus import check_corpus, check_replay, golden_path
+from shroudstone.replay import AIType, Faction
+from shroudstone.synthetic import ReplayBuilder
+
+CORPUS_DIR = Path(__file__).parent / "corpus"
+
+
+def ladder_1v1_surrender() -> ReplayBuilder:
+    builder = ReplayBuilder(map_name="Broken Crown")
+    alice = builder.add_player("Alice", Faction.vanguard, uuid=UUID(int=0xA11CE))
+    bob = builder.add_player("Bob", Faction.infernals, uuid=UUID(int=0xB0B))
+    builder.start()
+    builder.command(alice, 10)
+    builder.command(bob, 12)
+    builder.surrender(bob, 480)
+    builder.leave(alice, 485)
+    return builder
+
+
+def ladder_1v1_early_leave() -> ReplayBuilder:
+    builder = ReplayBuilder(map_name="Broken Crown")
+    alice = builder.add_player("Alice", Faction.catgirls, uuid=UUID(int=0xA11CE))
+    bob = builder.add_player("Bob", Faction.vanguard, uuid=UUID(int=0xB0B))
+    builder.start()
+    builder.leave(bob, 20)
+    builder.leave(alice, 25)
+    return builder
+
+
+def custom_mirror_with_spectator() -> ReplayBuilder:
+    builder = ReplayBuilder(map_name="Broken Crown", ladder=False)
+    alice = builder.add_player("Alice", Faction.vanguard, slot=2)
+    bob = builder.add_player("Alice", Faction.vanguard, slot=1, discriminator="4242")
+    carol = builder.add_spectator("Carol")
+    builder.start()
+    builder.command(alice, 30)
+    builder.command(bob, 31)
+    builder.surrender(alice, 600)
+    builder.leave(bob, 601)
+    builder.leave(carol, 602)
+    return builder
+
+
+def coop_with_ai() -> ReplayBuilder:
+    builder = ReplayBuilder(map_name="WreckHavoc", ladder=False)
+    alice = builder.add_player("Alice", Faction.vanguard)
+    bob = builder.add_player("Bob", Faction.infernals)
+    builder.add_ai(AIType.MurderBotJr, Faction.catgirls)
+    builder.start()
+    builder.command(alice, 5)
+    builder.command(bob, 6)
+    builder.leave(alice, 900)
+    builder.leave(bob, 900)
+    return builder
+
+
+SCENARIOS: Dict[str, Callable[[], ReplayBuilder]] = {
+    f.__name__: f
+    for f in [
+        ladder_1v1_surrender,
+        ladder_1v1_early_leave,
+        custom_mirror_with_spectator,
+        coop_with_ai,
+    ]
+}
+
+
+def main():
+    CORPUS_DIR.mkdir(exist_ok=True)
+    for name, scenario in SCENARIOS.items():
+        replay = CORPUS_DIR / f"{name}.SGReplay"
+        scenario().write(replay)
+        if not golden_path(replay).exists():
+            check_replay(replay, update=True)
+    for result in check_corpus(CORPUS_DIR):
+        print(f"{result.status}: {result.path.name}")
+
+if __name__ == "__main__":
+    main()
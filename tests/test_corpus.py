@@ -0,0 +1,20 @@
+from shroudstone.corpus import check_corpus
+
+from make_corpus import CORPUS_DIR, SCENARIOS
+
+
+def test_every_scenario_is_in_the_corpus():
+    replays = {p.name for p in CORPUS_DIR.glob("*.SGReplay")}
+    missing = [f"{name}.SGReplay" for name in SCENARIOS]
+    missing = [name for name in missing if name not in replays]
+    assert not missing, "Run tests/make_corpus.py to generate " + ", ".join(missing)
+
+
+def test_corpus_matches_golden_files():
+    results = check_corpus(CORPUS_DIR)
+    problems = [
+        f"{r.status.upper()}: {r.path.name}\n{r.diff or ''}"
+        for r in results
+        if r.status != "ok"
+    ]
+    assert not problems, "\n".join(problems)
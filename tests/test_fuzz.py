@@ -0,0 +1,10 @@
+from shroudstone.synthetic import fuzz_simulator
+
+
+def test_fuzz_simulator():
+    failures = fuzz_simulator(iterations=200, seed=0)
+    report = [
+        f"Seed {failure.seed}:\n" + "\n".join(f"  {p}" for p in failure.problems)
+        for failure in failures
+    ]
+    assert not failures, "\n".join(report)